@@ -0,0 +1,7111 @@
+//! A harness for hammering one SQLite database from many tokio tasks at
+//! once, behind the `mult_sql` binary.
+//!
+//! `run` is the whole command line tool. To drive an insert run from code,
+//! open a `DB` with `DbOptions::default()` and a `Schema`, create its table
+//! with `DB::migrate`, pool connections with `DbPool::open` and hand
+//! `run_insertion` a `Workload::Fixed` of users from `create_users`.
+
+mod migrations;
+
+use anyhow::{anyhow, Context};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use hdrhistogram::Histogram;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use migrations::Migrated;
+use rand::distributions::{Alphanumeric, WeightedIndex};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{config::DbConfig, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rusqlite::Connection;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+
+const N_WORKERS: usize = 4;
+const N_USERS: usize = 10_000;
+/// Exit code for a run stopped by Ctrl-C, following the shell's 128 + SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code for any error not covered below, including bad arguments.
+const EXIT_ERROR: i32 = 1;
+/// Exit code when the run finished but some inserts failed.
+const EXIT_PARTIAL_FAILURE: i32 = 2;
+/// Exit code when the database couldn't be opened or stayed locked.
+const EXIT_DB_UNAVAILABLE: i32 = 3;
+const DEFAULT_DB_PATH: &str = "test.db";
+const DEFAULT_TABLE: &str = "users";
+const MEMORY_DB: &str = ":memory:";
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// How many insert errors each worker prints before only counting them.
+const MAX_LOGGED_ERRORS: usize = 5;
+/// SQLite's default limit on bound parameters in a single statement.
+const MAX_BOUND_PARAMS: usize = 999;
+/// Upper bound for the backoff between busy retries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How many streamed rows may be buffered before the query waits for the reader.
+const STREAM_BUFFER: usize = 1024;
+/// How long `--timeout-secs` waits for workers to commit after cancelling them
+/// before the command is dropped outright.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+/// How often the progress bar picks up the workers' row count.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// Earliest generated `created_at`, 2024-01-01T00:00:00Z.
+const CREATED_AT_BASE: i64 = 1_704_067_200;
+/// Length of generated names and extra column values.
+const NAME_LEN: usize = 15;
+/// Largest `--with-payload`. Generated users are all held in memory before a
+/// run starts, so a run needs about `--count` times this much.
+const MAX_PAYLOAD_BYTES: usize = 1 << 20;
+/// Every zstd frame starts with these bytes, which tells a compressed
+/// payload from a plain one on read.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Share of inserts hitting an existing name above which the summary
+/// suggests longer names.
+const COLLISION_WARN_RATE: f64 = 0.01;
+/// Share of `--target-rate` a run must reach before the summary stops
+/// reporting it as short.
+const TARGET_RATE_SLACK: f64 = 0.95;
+
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Commands,
+    #[arg(
+        short,
+        long,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    workers: Option<usize>,
+    /// Number of connections the workers are spread over (defaults to the
+    /// worker count). SQLite only allows one writer at a time, so extra
+    /// connections mostly help readers; writers still queue on the lock.
+    #[arg(long)]
+    pool_size: Option<usize>,
+    /// Upper bound on the pool size, so a large worker count doesn't run out
+    /// of file descriptors; workers beyond it share connections
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_CONNECTIONS,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_connections: usize,
+    /// Path to the database file, or `:memory:` for an in-memory database
+    #[arg(long, default_value = DEFAULT_DB_PATH)]
+    db_path: PathBuf,
+    /// Log filter such as `info` or `mult_sql=debug`; overrides RUST_LOG
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// Print the SQL the command would run instead of opening the database
+    #[arg(long)]
+    dry_run: bool,
+    /// Expect the table to exist already instead of creating or migrating it
+    #[arg(long)]
+    no_create: bool,
+    /// Stop the command after this many seconds; inserts commit what they
+    /// have first. No limit by default
+    #[arg(long, value_name = "N")]
+    timeout_secs: Option<u64>,
+    /// TOML file with a workload profile; flags given on the command line win
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Time every insert, select, update and delete call and print the
+    /// calls and time per kind at the end
+    #[arg(long)]
+    profile: bool,
+    /// Serve the calls per statement kind so far and the elapsed time as
+    /// JSON over HTTP on this address while the command runs
+    #[arg(long, value_name = "ADDR")]
+    status_addr: Option<SocketAddr>,
+    /// Print a summary once the command is done: what ran, the rows it
+    /// touched, how long it took and whether it succeeded. `--summary=json`
+    /// prints it as a single JSON object
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    summary: Option<SummaryFormat>,
+    #[command(flatten)]
+    db_options: DbOptions,
+    #[command(flatten)]
+    schema: SchemaArgs,
+}
+
+/// A workload profile loaded with `--config`. Every field is optional and only
+/// fills in what wasn't passed on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    workers: Option<usize>,
+    count: Option<usize>,
+    journal_mode: Option<JournalMode>,
+    synchronous: Option<Synchronous>,
+    tx_size: Option<usize>,
+    on_conflict: Option<OnConflict>,
+}
+
+impl Config {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+        if config.workers == Some(0) {
+            return Err(anyhow!(
+                "invalid config {}: workers must be at least 1",
+                path.display()
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Copies every configured value into `cli` unless `matches` shows the
+    /// same option was given on the command line.
+    fn apply(self, cli: &mut Args, matches: &clap::ArgMatches) {
+        let from_cli = |matches: &clap::ArgMatches, id: &str| {
+            matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine)
+        };
+
+        cli.workers = cli.workers.or(self.workers);
+        cli.db_options.synchronous = cli.db_options.synchronous.or(self.synchronous);
+        if let Some(mode) = self
+            .journal_mode
+            .filter(|_| !from_cli(matches, "journal_mode"))
+        {
+            cli.db_options.journal_mode = mode;
+        }
+
+        let insert = match &mut cli.command {
+            Commands::Insert(insert) | Commands::Benchmark { insert, .. } => insert,
+            _ => return,
+        };
+        let Some((_, matches)) = matches.subcommand() else {
+            return;
+        };
+        insert.tx_size = insert.tx_size.or(self.tx_size);
+        if let Some(count) = self.count.filter(|_| !from_cli(matches, "count")) {
+            insert.count = count;
+        }
+        if let Some(policy) = self
+            .on_conflict
+            .filter(|_| !from_cli(matches, "on_conflict"))
+        {
+            insert.on_conflict = policy;
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct SchemaArgs {
+    /// Table the users are stored in, optionally qualified as `ALIAS.TABLE`
+    /// for a database given with --attach
+    #[arg(long, default_value = DEFAULT_TABLE, value_parser = parse_table_name)]
+    table: String,
+    /// Number of additional TEXT columns, filled with random data on insert
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    extra_cols: usize,
+    /// Length of generated names. `name` is UNIQUE, so short names collide
+    /// often and those inserts are ignored or fail, per --on-conflict
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = NAME_LEN,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    name_len: usize,
+    /// Create the table without the UNIQUE constraint on `name`, to measure
+    /// inserts without maintaining its index. Duplicate names are then kept
+    #[arg(long)]
+    no_unique: bool,
+    /// Create the table with `CHECK(length(name) <= N)`, to measure what
+    /// evaluating it costs per insert. Longer names are rejected
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_name_len: Option<usize>,
+    /// Add a `payload BLOB` column filled with BYTES random bytes per user, at
+    /// most 1 MiB. Users are generated up front, so an insert holds about
+    /// --count × BYTES in memory. Large payloads spill into overflow pages
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=MAX_PAYLOAD_BYTES as u64)
+    )]
+    with_payload: Option<usize>,
+    /// zstd-compress each payload before inserting it; selects decompress it
+    /// again
+    #[arg(long, requires = "with_payload")]
+    compress: bool,
+    /// Declare `name` as TEXT or BLOB and bind the generated names as a
+    /// string or as its UTF-8 bytes, to compare the two storage classes.
+    /// Only affects a newly created table
+    #[arg(long, value_enum, default_value_t = NameType::Text)]
+    name_type: NameType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NameType {
+    Text,
+    Blob,
+}
+
+impl NameType {
+    fn as_sql(self) -> &'static str {
+        match self {
+            NameType::Text => "TEXT",
+            NameType::Blob => "BLOB",
+        }
+    }
+}
+
+impl SchemaArgs {
+    fn user_shape(&self) -> UserShape {
+        UserShape {
+            extra_cols: self.extra_cols,
+            name_len: self.name_len,
+            payload: self.with_payload.map(|bytes| PayloadOptions {
+                bytes,
+                compress: self.compress,
+            }),
+        }
+    }
+}
+
+/// What generated users look like besides their random values.
+#[derive(Clone, Copy, Debug)]
+pub struct UserShape {
+    pub extra_cols: usize,
+    pub name_len: usize,
+    pub payload: Option<PayloadOptions>,
+}
+
+impl Default for UserShape {
+    fn default() -> Self {
+        Self {
+            extra_cols: 0,
+            name_len: NAME_LEN,
+            payload: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PayloadOptions {
+    pub bytes: usize,
+    pub compress: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OutputFormat {
+    /// Rust debug formatting, one row at a time
+    Debug,
+    /// A single JSON array of all rows, with every column of the table
+    Json,
+    /// One JSON object per line, written as the rows arrive
+    Jsonl,
+    /// An `id,name,email,created_at` header followed by one line per row
+    Csv,
+}
+
+/// The order `select` returns rows in.
+#[derive(Clone, Debug)]
+enum RowOrder {
+    /// Whatever order the table is scanned in, which is rowid order.
+    Scan,
+    Random,
+    /// By a column already checked to be a plain identifier.
+    Column {
+        name: String,
+        desc: bool,
+    },
+}
+
+impl RowOrder {
+    fn new(sample: bool, order_by: Option<String>, desc: bool) -> Self {
+        match order_by {
+            Some(name) => RowOrder::Column { name, desc },
+            None if sample => RowOrder::Random,
+            None => RowOrder::Scan,
+        }
+    }
+
+    fn sql(&self) -> String {
+        match self {
+            RowOrder::Scan => String::new(),
+            RowOrder::Random => " ORDER BY RANDOM()".to_owned(),
+            RowOrder::Column { name, desc } => {
+                format!(" ORDER BY {}{}", name, if *desc { " DESC" } else { "" })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SummaryFormat {
+    Text,
+    Json,
+}
+
+/// What `--summary` reports about a finished command.
+struct CommandSummary<'a> {
+    operation: &'a str,
+    /// Rows the command inserted, read, updated, deleted or counted, when it
+    /// knows.
+    rows: Option<usize>,
+    elapsed: Duration,
+    error: Option<&'a anyhow::Error>,
+}
+
+impl CommandSummary<'_> {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "operation": self.operation,
+            "rows": self.rows,
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+            "status": if self.error.is_some() { "failed" } else { "ok" },
+            "error": self.error.map(ToString::to_string),
+        })
+    }
+
+    fn print(&self, format: SummaryFormat) {
+        if let SummaryFormat::Json = format {
+            println!("{}", self.to_json());
+            return;
+        }
+
+        let rows = match self.rows {
+            Some(rows) => format!("{} rows", rows),
+            None => "rows n/a".to_owned(),
+        };
+        let status = match self.error {
+            Some(e) => format!("failed: {}", e),
+            None => "ok".to_owned(),
+        };
+        println!(
+            "Summary: {}, {}, {:.3?}, {}",
+            self.operation, rows, self.elapsed, status
+        );
+    }
+}
+
+/// Settings applied to every connection right after it is opened.
+#[derive(clap::Args, Clone)]
+pub struct DbOptions {
+    /// Journal mode to set on file databases
+    #[arg(long, value_enum, default_value_t = JournalMode::Wal)]
+    pub journal_mode: JournalMode,
+    /// `PRAGMA synchronous` level; SQLite's default (full) when absent
+    #[arg(long, value_enum)]
+    pub synchronous: Option<Synchronous>,
+    /// How long a connection waits on a locked database before giving up
+    #[arg(long, value_name = "MS", default_value_t = 5000)]
+    pub busy_timeout_ms: u64,
+    /// How many times an insert is retried when the database is busy or locked
+    #[arg(long, default_value_t = 5)]
+    pub max_retries: u32,
+    /// Delay before the first busy retry; it doubles on every further attempt
+    #[arg(long, value_name = "MS", default_value_t = 1)]
+    pub retry_base_ms: u64,
+    /// How many times opening the database is retried while another process
+    /// holds a lock on it; each attempt waits up to --busy-timeout-ms first
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub open_retries: u32,
+    /// SQLite extension to load into every connection; may be repeated
+    #[arg(long = "load-extension", value_name = "PATH")]
+    pub extensions: Vec<PathBuf>,
+    /// Attach another database file to every connection; may be repeated.
+    /// Its tables can then be used with `--table ALIAS.TABLE`
+    #[arg(long = "attach", value_name = "ALIAS=PATH", value_parser = parse_attachment)]
+    pub attachments: Vec<Attachment>,
+    /// `PRAGMA mmap_size` for every connection; SQLite may cap it at its
+    /// compile-time maximum, so the effective value is logged
+    #[arg(long, value_name = "BYTES")]
+    pub mmap_size: Option<u64>,
+    /// `PRAGMA cache_size` for every connection: positive N is a number of
+    /// pages, negative N a size in KiB
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    pub cache_size: Option<i64>,
+    /// `PRAGMA page_size` for a new database file: a power of two from 512 to
+    /// 65536. An existing database keeps its page size, so this is an error
+    /// there unless it already matches
+    #[arg(long, value_name = "BYTES", value_parser = parse_page_size)]
+    pub page_size: Option<u32>,
+    /// How many prepared statements each connection keeps cached
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    pub stmt_cache_capacity: usize,
+    /// `PRAGMA wal_autocheckpoint` for every connection: checkpoint once the
+    /// WAL holds N pages, or never automatically with 0. SQLite's default is
+    /// 1000
+    #[arg(long, value_name = "N")]
+    pub wal_autocheckpoint: Option<u32>,
+    /// Leave the WAL in place when the last connection closes instead of
+    /// checkpointing it, so that cost isn't paid at the end of the run
+    #[arg(long)]
+    pub no_checkpoint_on_close: bool,
+    /// Open every connection in SQLite's shared-cache mode, so the
+    /// connections to one file share a page cache and lock per table.
+    /// SQLite discourages shared cache for most uses, WAL usually serves
+    /// better, and contention shows up as SQLITE_LOCKED, which the busy
+    /// timeout does not wait out
+    #[arg(long)]
+    pub shared_cache: bool,
+    /// Set `PRAGMA read_uncommitted` so that, in shared-cache mode, readers
+    /// skip the table locks and may see other connections' uncommitted rows
+    #[arg(long, requires = "shared_cache")]
+    pub read_uncommitted: bool,
+}
+
+impl DbOptions {
+    fn open_flags(&self) -> OpenFlags {
+        if self.shared_cache {
+            OpenFlags::default() | OpenFlags::SQLITE_OPEN_SHARED_CACHE
+        } else {
+            OpenFlags::default()
+        }
+    }
+}
+
+/// The same defaults as on the command line.
+impl Default for DbOptions {
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            options: DbOptions,
+        }
+        Defaults::parse_from(["mult_sql"]).options
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub alias: String,
+    pub path: PathBuf,
+}
+
+fn parse_attachment(s: &str) -> Result<Attachment, String> {
+    let (alias, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not in ALIAS=PATH form", s))?;
+    if path.is_empty() {
+        return Err(format!("no path given for `{}`", alias));
+    }
+
+    Ok(Attachment {
+        alias: parse_identifier(alias)?,
+        path: PathBuf::from(path),
+    })
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+
+    /// Maps the integer `PRAGMA synchronous` reports back to a level.
+    fn from_level(level: i64) -> Option<Self> {
+        match level {
+            0 => Some(Synchronous::Off),
+            1 => Some(Synchronous::Normal),
+            2 => Some(Synchronous::Full),
+            3 => Some(Synchronous::Extra),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    Insert(InsertArgs),
+    /// Run the insertion once per worker count, and per transaction size
+    /// with --tx-size-set, clearing the table in between
+    Benchmark {
+        /// Worker counts to measure, comma separated
+        #[arg(
+            long,
+            value_delimiter = ',',
+            default_values_t = [1, 2, 4, 8, 16],
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        worker_set: Vec<usize>,
+        /// Transaction sizes to measure with every worker count, comma
+        /// separated (defaults to --tx-size alone)
+        #[arg(
+            long,
+            visible_alias = "commit-every-set",
+            value_name = "N,...",
+            value_delimiter = ',',
+            conflicts_with = "tx_size",
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        tx_size_set: Vec<usize>,
+        /// Print the results as CSV
+        #[arg(long)]
+        csv: bool,
+        #[command(flatten)]
+        insert: InsertArgs,
+    },
+    Select {
+        /// How to print the selected rows
+        #[arg(long, value_enum, default_value_t = OutputFormat::Debug)]
+        format: OutputFormat,
+        /// Write the rows to this file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Return at most N rows
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        /// Pick the --limit rows at random instead of in rowid order
+        #[arg(long, requires = "limit")]
+        sample: bool,
+        /// Sort the rows by this column, which must exist in the table
+        #[arg(long, value_name = "COLUMN", value_parser = parse_identifier, conflicts_with = "sample")]
+        order_by: Option<String>,
+        /// Sort in descending order
+        #[arg(long, requires = "order_by")]
+        desc: bool,
+        /// Print the query plan to stderr first, showing whether the rows
+        /// come out of an index in order or need a temporary B-tree sort
+        #[arg(long)]
+        show_plan: bool,
+    },
+    /// Read the whole table with every worker selecting its own slice of the
+    /// id range at the same time, to see whether reads scale with workers
+    ParallelSelect,
+    /// Delete every row, or only those matching --where or --id
+    Delete {
+        /// SQL expression used as the WHERE clause, e.g. "name LIKE ?1".
+        /// It is inserted verbatim, so it is open to SQL injection; only pass
+        /// trusted input and bind values with --param.
+        #[arg(long = "where", value_name = "EXPR")]
+        filter: Option<String>,
+        /// Value bound to the next `?` placeholder in the WHERE expression
+        #[arg(long = "param", value_name = "VALUE", requires = "filter")]
+        params: Vec<String>,
+        /// Delete the single row with this id
+        #[arg(long, conflicts_with = "filter")]
+        id: Option<i64>,
+    },
+    /// Rename users, either all of them with --set-prefix or one with --id/--name
+    #[command(group(clap::ArgGroup::new("target").required(true).args(["set_prefix", "id"])))]
+    Update {
+        /// Prepend this to every name
+        #[arg(long, value_name = "STR", conflicts_with = "id")]
+        set_prefix: Option<String>,
+        /// Row to rename
+        #[arg(long, requires = "name")]
+        id: Option<i64>,
+        /// New name for the row given by --id
+        #[arg(long, requires = "id")]
+        name: Option<String>,
+        /// Split the prefix updates across the workers by id
+        #[arg(long, conflicts_with = "id")]
+        concurrent: bool,
+    },
+    /// Drop the table and create it again empty, in one transaction
+    Reset,
+    /// Rebuild the database file to reclaim free pages
+    Vacuum {
+        /// Run `PRAGMA incremental_vacuum` instead, which only frees pages
+        /// when the database was created with `auto_vacuum=INCREMENTAL`
+        #[arg(long)]
+        incremental: bool,
+    },
+    /// Write a compacted copy of the database to DEST with `VACUUM INTO`,
+    /// which only holds a read transaction so readers carry on meanwhile
+    Backup {
+        dest: PathBuf,
+        /// Replace DEST if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Checkpoint the WAL into the database file and truncate it, reporting
+    /// its size before and after
+    Checkpoint,
+    /// Print the database's page counts, page size, journal mode and file size
+    Stats {
+        /// Print them as a single JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Create an index on one of the table's columns
+    CreateIndex {
+        #[arg(value_parser = parse_identifier)]
+        column: String,
+    },
+    /// Print SQLite's query plan for a statement
+    Explain {
+        query: String,
+    },
+    /// Run inserting and selecting tasks side by side for a fixed duration.
+    /// With `--writers 0` it measures concurrent reads alone
+    Mix(MixArgs),
+    /// Have every worker pick random inserts, selects, updates and deletes
+    /// for a while, then check the table. Unexpected errors, panics and a
+    /// failed integrity check are reported with the seed to replay them
+    Fuzz(FuzzArgs),
+    /// Look a user up by name, or time --count lookups of random existing names
+    Lookup {
+        #[arg(required_unless_present = "count")]
+        name: Option<String>,
+        /// Number of random lookups to run instead of a single one
+        #[arg(short, long, conflicts_with = "name")]
+        count: Option<usize>,
+    },
+    /// Print every name held by more than one row, or "no duplicates", and
+    /// fail if there are any. Useful after --no-unique or an import
+    VerifyUnique,
+    /// Print the number of rows in the table
+    Count {
+        /// SQL expression appended as a WHERE clause, e.g. "name LIKE ?1".
+        /// It is inserted verbatim, so only pass trusted input.
+        #[arg(long = "where", value_name = "EXPR")]
+        filter: Option<String>,
+        /// Value bound to the next `?` placeholder in the WHERE expression
+        #[arg(long = "param", value_name = "VALUE", requires = "filter")]
+        params: Vec<String>,
+    },
+    /// Insert --per-user posts for every existing user, spread over the
+    /// workers. Posts reference their user through a foreign key
+    InsertPosts {
+        /// Posts to insert for each user
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        per_user: usize,
+        /// Insert the posts for this user id only, without checking that the
+        /// user exists, so a missing one shows up as a foreign key violation
+        #[arg(long)]
+        user_id: Option<i64>,
+        /// Split each worker's inserts into transactions of at most N rows
+        /// (defaults to a single transaction per worker)
+        #[arg(long, value_name = "N")]
+        tx_size: Option<usize>,
+        /// Print the run's metrics as a single JSON object
+        #[arg(long)]
+        json_metrics: bool,
+        /// Skip the per-row "inserted" log lines
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Read SQL statements from stdin and run them, printing rows as JSON.
+    /// A statement may span lines and ends with `;`; `.quit` leaves
+    Repl,
+    /// Insert the users from a CSV file with a header naming at least a
+    /// `name` column, such as one written by `select --format csv`
+    Import {
+        path: PathBuf,
+        /// Report malformed lines and carry on instead of aborting the import
+        #[arg(long)]
+        skip_bad: bool,
+        /// Rows inserted per transaction
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 1000,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        tx_size: usize,
+        /// How to handle a name that already exists in the table
+        #[arg(long, value_enum, default_value_t = OnConflict::Fail)]
+        on_conflict: OnConflict,
+    },
+}
+
+impl Commands {
+    /// Whether the command stops early, keeping what it committed, when
+    /// Ctrl-C cancels the run. The others are left to the default SIGINT
+    /// handling, which ends the process right away.
+    fn stops_on_ctrl_c(&self) -> bool {
+        match self {
+            Commands::Insert(_)
+            | Commands::Benchmark { .. }
+            | Commands::Select { .. }
+            | Commands::ParallelSelect
+            | Commands::Mix(_)
+            | Commands::Fuzz(_)
+            | Commands::InsertPosts { .. }
+            | Commands::Repl
+            | Commands::Import { .. } => true,
+            Commands::Update { concurrent, .. } => *concurrent,
+            Commands::Lookup { count, .. } => count.is_some(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(clap::Args, Clone, Copy)]
+struct MixArgs {
+    /// How long to run the workload
+    #[arg(long, default_value_t = 10)]
+    duration_secs: u64,
+    /// Number of tasks repeatedly selecting the whole table
+    #[arg(long, default_value_t = 2)]
+    readers: usize,
+    /// Number of tasks inserting newly generated users
+    #[arg(long, default_value_t = 2)]
+    writers: usize,
+    /// Rows each writer inserts per transaction
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    tx_size: usize,
+    /// Have readers run `COUNT(*)` instead of reading every row
+    #[arg(long)]
+    count_only: bool,
+    /// Delete every row from another task this many seconds in, then check
+    /// that the table passes `PRAGMA integrity_check` and holds exactly the
+    /// rows inserted since minus those deleted
+    #[arg(long, value_name = "N")]
+    delete_after_secs: Option<u64>,
+}
+
+#[derive(clap::Args, Clone, Copy)]
+struct FuzzArgs {
+    /// How long to run the workload
+    #[arg(long, default_value_t = 10)]
+    duration_secs: u64,
+    /// Seed for every worker's choices (a random seed is picked and logged
+    /// when omitted)
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Relative weight of inserting a new user
+    #[arg(long, value_name = "N", default_value_t = 70)]
+    insert_weight: u32,
+    /// Relative weight of looking up a random name
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    select_weight: u32,
+    /// Relative weight of renaming a random id
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    update_weight: u32,
+    /// Relative weight of deleting a random id
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    delete_weight: u32,
+}
+
+impl FuzzArgs {
+    /// The weights in `StatementKind::ALL` order.
+    fn weights(&self) -> [u32; 4] {
+        [
+            self.insert_weight,
+            self.select_weight,
+            self.update_weight,
+            self.delete_weight,
+        ]
+    }
+}
+
+#[derive(clap::Args, Clone)]
+pub struct InsertArgs {
+    /// Number of users to generate and insert
+    #[arg(short, long, default_value_t = N_USERS)]
+    pub count: usize,
+    /// Read the names to insert from a file, one per line, instead of
+    /// generating them (overrides --count)
+    #[arg(long, value_name = "PATH")]
+    pub from_file: Option<PathBuf>,
+    /// Keep generating and inserting users for this many seconds instead of
+    /// a fixed count
+    #[arg(long, value_name = "N", conflicts_with_all = ["count", "from_file"])]
+    pub duration_secs: Option<u64>,
+    /// Commit every N rows, starting a new transaction for the next ones
+    /// (defaults to a single transaction per worker, or one statement per
+    /// transaction with --duration-secs or --target-rate). Independent of
+    /// --rows-per-stmt, which only sets how many rows each INSERT binds
+    #[arg(long, visible_alias = "commit-every", value_name = "N")]
+    pub tx_size: Option<usize>,
+    /// How to handle a name that already exists in the table
+    #[arg(long, value_enum, default_value_t = OnConflict::Fail)]
+    pub on_conflict: OnConflict,
+    /// How users are handed out to the workers
+    #[arg(long, value_enum, default_value_t = Distribution::Static)]
+    pub distribution: Distribution,
+    /// Rows bound into each multi-row INSERT statement (capped so a statement
+    /// stays under SQLite's 999 parameter limit)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub rows_per_stmt: usize,
+    /// Limit how many insert transactions may be in flight across all
+    /// workers at once (unlimited by default)
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub max_inflight: Option<usize>,
+    /// Seed for the name generator, so a run's data can be reproduced
+    /// (a random seed is picked and logged when omitted)
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Throwaway runs before the measured one, clearing the table after each.
+    /// Their timings are logged as discarded and never reported
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub warmup: usize,
+    /// Print the run's metrics as a single JSON object
+    #[arg(long)]
+    pub json_metrics: bool,
+    /// Abort the run once more than N inserts have used up all their busy
+    /// retries (--max-retries), a sign the database can't keep up with this
+    /// many writers
+    #[arg(long, value_name = "N")]
+    pub fail_after_retries: Option<usize>,
+    /// Time every insert transaction, from BEGIN to COMMIT including busy
+    /// retries, and report per-transaction latency percentiles
+    #[arg(long)]
+    pub latency: bool,
+    /// How the workers talk to SQLite
+    #[arg(long, value_enum, default_value_t = Backend::TokioRusqlite)]
+    pub backend: Backend,
+    /// Once the workers are done, copy the WAL into the database with
+    /// `wal_checkpoint(FULL)` and report that time apart from the inserts.
+    /// Pair it with --wal-autocheckpoint 0 so no checkpoint runs before
+    #[arg(long)]
+    pub checkpoint_after: bool,
+    /// Stop every worker and fail on the first insert error instead of
+    /// counting failed rows and carrying on
+    #[arg(long)]
+    pub strict: bool,
+    /// Record the database file's size this often during the measured run
+    /// and print the samples as CSV at the end (or in --json-metrics)
+    #[arg(
+        long,
+        value_name = "MS",
+        value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    pub sample_interval_ms: Option<u64>,
+    /// Pace the workers so that together they insert about this many rows
+    /// per second, each taking an equal share. A worker sends a whole
+    /// transaction at once, so a small --tx-size gives a smoother pace
+    #[arg(
+        long,
+        value_name = "ROWS_PER_SEC",
+        value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    pub target_rate: Option<u64>,
+    /// Which BEGIN each worker's insert transaction starts with, deciding
+    /// when it takes the write lock
+    #[arg(long, value_enum, default_value_t = BeginMode::Deferred)]
+    pub begin_mode: BeginMode,
+    /// Keep the rows already in the table, or delete them before inserting.
+    /// benchmark always deletes them before every run
+    #[arg(long, value_enum, default_value_t = InsertMode::Append)]
+    pub insert_mode: InsertMode,
+    /// Skip the per-row "inserted" log lines, even at debug level, so they
+    /// don't skew the timings
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+/// The same defaults as on the command line.
+impl Default for InsertArgs {
+    fn default() -> Self {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            args: InsertArgs,
+        }
+        Defaults::parse_from(["insert"]).args
+    }
+}
+
+impl InsertArgs {
+    fn insert_options(&self) -> InsertOptions {
+        InsertOptions {
+            on_conflict: self.on_conflict,
+            rows_per_stmt: self.rows_per_stmt,
+            begin_mode: self.begin_mode,
+        }
+    }
+}
+
+/// How `DB::insert_many` writes a batch.
+#[derive(Clone, Copy, Debug)]
+pub struct InsertOptions {
+    pub on_conflict: OnConflict,
+    pub rows_per_stmt: usize,
+    pub begin_mode: BeginMode,
+}
+
+/// How an insert transaction begins. In WAL mode readers never block a
+/// writer or the other way round, so the modes only differ in when writers
+/// queue up for the single write lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BeginMode {
+    /// BEGIN DEFERRED: take the write lock at the first INSERT. In WAL mode a
+    /// transaction that read before writing gets SQLITE_BUSY without waiting
+    /// if another writer committed in between
+    Deferred,
+    /// BEGIN IMMEDIATE: take the write lock at BEGIN, waiting out
+    /// --busy-timeout for it, so the transaction can't fail to upgrade later
+    Immediate,
+    /// BEGIN EXCLUSIVE: like immediate in WAL mode; in the rollback journal
+    /// modes it also locks out readers until the commit
+    Exclusive,
+}
+
+impl BeginMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            BeginMode::Deferred => "deferred",
+            BeginMode::Immediate => "immediate",
+            BeginMode::Exclusive => "exclusive",
+        }
+    }
+
+    fn behavior(self) -> rusqlite::TransactionBehavior {
+        match self {
+            BeginMode::Deferred => rusqlite::TransactionBehavior::Deferred,
+            BeginMode::Immediate => rusqlite::TransactionBehavior::Immediate,
+            BeginMode::Exclusive => rusqlite::TransactionBehavior::Exclusive,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InsertMode {
+    /// Insert next to the existing rows, which may collide on a UNIQUE name
+    Append,
+    /// Delete every existing row first, for a fresh run
+    Truncate,
+}
+
+impl InsertMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            InsertMode::Append => "append",
+            InsertMode::Truncate => "truncate",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// tokio-rusqlite, which runs every call on its connection's own
+    /// background thread
+    TokioRusqlite,
+    /// Plain rusqlite connections called from tokio's blocking thread pool
+    Blocking,
+}
+
+impl Backend {
+    fn as_str(self) -> &'static str {
+        match self {
+            Backend::TokioRusqlite => "tokio-rusqlite",
+            Backend::Blocking => "blocking",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Distribution {
+    /// Split the users into one fixed slice per worker up front
+    Static,
+    /// Feed all workers from one shared channel; whichever worker is free
+    /// takes the next --tx-size users
+    Dynamic,
+}
+
+/// Where a worker takes its next transaction's worth of entities from.
+pub enum WorkSource<E> {
+    Static(std::vec::IntoIter<E>),
+    Dynamic(Arc<tokio::sync::Mutex<mpsc::Receiver<E>>>),
+    Generated {
+        deadline: Instant,
+        rng: Box<StdRng>,
+        generate: Generator<E>,
+    },
+}
+
+/// Produces `n` new entities from the given RNG.
+pub type Generator<E> = Arc<dyn Fn(usize, &mut StdRng) -> Vec<E> + Send + Sync>;
+
+/// What one insertion run works through.
+#[derive(Clone)]
+pub enum Workload<E> {
+    /// A fixed set of entities, split between the workers.
+    Fixed(Vec<E>),
+    /// Every worker generates entities from its own RNG, seeded from `seed`,
+    /// until `duration` has passed. `--distribution` doesn't apply.
+    Timed {
+        duration: Duration,
+        seed: u64,
+        generate: Generator<E>,
+    },
+}
+
+impl<E: Clone + Send + 'static> Workload<E> {
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Workload::Fixed(entities) => Some(entities.len()),
+            Workload::Timed { .. } => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Hands the work out to `n_workers`, treating `start` as the beginning
+    /// of a timed run.
+    pub fn into_sources(
+        self,
+        n_workers: usize,
+        distribution: Distribution,
+        start: Instant,
+    ) -> Vec<WorkSource<E>> {
+        match (self, distribution) {
+            (Workload::Fixed(entities), Distribution::Static) => partition(entities, n_workers)
+                .into_iter()
+                .map(|slice| WorkSource::Static(slice.into_iter()))
+                .collect(),
+            (Workload::Fixed(entities), Distribution::Dynamic) => {
+                let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+                tokio::spawn(async move {
+                    for entity in entities {
+                        // Every worker has stopped, e.g. after a cancel.
+                        if sender.send(entity).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+                (0..n_workers)
+                    .map(|_| WorkSource::Dynamic(Arc::clone(&receiver)))
+                    .collect()
+            }
+            (
+                Workload::Timed {
+                    duration,
+                    seed,
+                    generate,
+                },
+                _,
+            ) => (0..n_workers)
+                .map(|worker| WorkSource::Generated {
+                    deadline: start + duration,
+                    rng: Box::new(StdRng::seed_from_u64(seed.wrapping_add(worker as u64))),
+                    generate: Arc::clone(&generate),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Deals `entities` out to `n_workers` slices round-robin, so worker `i` gets
+/// entities `i`, `i + n_workers`, ... Every entity lands in exactly one slice;
+/// with more workers than entities the last slices are empty.
+fn partition<E>(entities: Vec<E>, n_workers: usize) -> Vec<Vec<E>> {
+    let mut slices: Vec<Vec<E>> = (0..n_workers)
+        .map(|_| Vec::with_capacity(entities.len().div_ceil(n_workers)))
+        .collect();
+    for (i, entity) in entities.into_iter().enumerate() {
+        slices[i % n_workers].push(entity);
+    }
+    slices
+}
+
+impl<E> WorkSource<E> {
+    /// Returns up to `size` entities, or none once the work has run out.
+    async fn next_chunk(&mut self, size: usize) -> Vec<E> {
+        match self {
+            WorkSource::Static(entities) => entities.by_ref().take(size).collect(),
+            WorkSource::Dynamic(receiver) => {
+                // The lock is held until the chunk is full, so other workers
+                // wait here rather than splitting a transaction between them.
+                let mut receiver = receiver.lock().await;
+                let mut chunk = Vec::with_capacity(size);
+                loop {
+                    let wanted = size - chunk.len();
+                    if wanted == 0 || receiver.recv_many(&mut chunk, wanted).await == 0 {
+                        break chunk;
+                    }
+                }
+            }
+            WorkSource::Generated {
+                deadline,
+                rng,
+                generate,
+            } => {
+                if Instant::now() >= *deadline {
+                    Vec::new()
+                } else {
+                    generate(size, rng)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Skip the conflicting row
+    Ignore,
+    /// Replace the existing row
+    Replace,
+    /// Abort the run with an error
+    Fail,
+}
+
+impl OnConflict {
+    fn insert_verb(self) -> &'static str {
+        match self {
+            OnConflict::Ignore => "INSERT OR IGNORE",
+            OnConflict::Replace => "INSERT OR REPLACE",
+            OnConflict::Fail => "INSERT",
+        }
+    }
+}
+
+/// Describes the table the harness works against: an `id` primary key, a
+/// `name` that is unique unless `--no-unique` is given and any number of extra
+/// TEXT columns.
+#[derive(Debug)]
+pub struct Schema {
+    table: String,
+    extra_columns: Vec<String>,
+    unique_names: bool,
+    /// Longest name the table's CHECK constraint allows, if it has one.
+    max_name_len: Option<usize>,
+    /// Whether rows carry a `payload` blob after the extra columns.
+    payload: bool,
+    /// How `name` is declared and bound.
+    name_type: NameType,
+}
+
+impl Schema {
+    pub fn new(
+        table: String,
+        extra_cols: usize,
+        unique_names: bool,
+        max_name_len: Option<usize>,
+        payload: bool,
+        name_type: NameType,
+    ) -> Self {
+        Self {
+            table,
+            extra_columns: (1..=extra_cols).map(|i| format!("extra_{}", i)).collect(),
+            unique_names,
+            max_name_len,
+            payload,
+            name_type,
+        }
+    }
+
+    fn create_table_sql(&self) -> String {
+        let mut name = format!("name {} NOT NULL", self.name_type.as_sql());
+        if self.unique_names {
+            name.push_str(" UNIQUE");
+        }
+        if let Some(len) = self.max_name_len {
+            name.push_str(&format!(" CHECK(length(name) <= {})", len));
+        }
+        let mut columns = vec![
+            "id INTEGER PRIMARY key".to_owned(),
+            name,
+            "email TEXT NOT NULL".to_owned(),
+            "created_at INTEGER NOT NULL".to_owned(),
+        ];
+        columns.extend(self.extra_columns.iter().map(|c| format!("{} TEXT", c)));
+        if self.payload {
+            columns.push("payload BLOB".to_owned());
+        }
+
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n    {}\n)",
+            self.table,
+            columns.join(",\n    ")
+        )
+    }
+
+    /// Number of values bound per inserted row.
+    fn insert_width(&self) -> usize {
+        3 + self.extra_columns.len() + usize::from(self.payload)
+    }
+
+    /// Builds an INSERT with `rows` parenthesised value groups. Every value
+    /// is a named parameter, see `insert_param`.
+    fn insert_sql(&self, on_conflict: OnConflict, rows: usize) -> String {
+        let columns: Vec<&str> = ["name", "email", "created_at"]
+            .into_iter()
+            .chain(self.extra_columns.iter().map(String::as_str))
+            .chain(self.payload.then_some("payload"))
+            .collect();
+        let groups: Vec<String> = (0..rows)
+            .map(|row| {
+                let placeholders: Vec<String> = columns
+                    .iter()
+                    .map(|column| Self::insert_param(column, row))
+                    .collect();
+                format!("({})", placeholders.join(", "))
+            })
+            .collect();
+
+        format!(
+            "{} INTO {} ({}) VALUES {}",
+            on_conflict.insert_verb(),
+            self.table,
+            columns.join(", "),
+            groups.join(",")
+        )
+    }
+
+    /// `name` as it is bound for this table's `--name-type`. SQLite never
+    /// compares TEXT equal to a BLOB, so lookups must bind it the same way.
+    fn name_value(&self, name: String) -> rusqlite::types::Value {
+        match self.name_type {
+            NameType::Text => rusqlite::types::Value::Text(name),
+            NameType::Blob => rusqlite::types::Value::Blob(name.into_bytes()),
+        }
+    }
+
+    /// The parameter `insert_sql` binds `column` of value group `row` to,
+    /// e.g. `:email_3`. Binding by name means a value can only land in its
+    /// own column, whatever order the columns are listed in.
+    fn insert_param(column: &str, row: usize) -> String {
+        format!(":{}_{}", column, row)
+    }
+
+    /// Columns are listed explicitly so the positional `row.get` calls keep
+    /// working whatever order the table's columns are in.
+    fn select_all_sql(&self) -> String {
+        let payload = if self.payload { ", payload" } else { "" };
+        format!(
+            "SELECT id, name, email, created_at{} FROM {}",
+            payload, self.table
+        )
+    }
+
+    /// Like `select_all_sql` in `order`, with the row limit bound to `?1`
+    /// when `limited`.
+    fn select_users_sql(&self, order: &RowOrder, limited: bool) -> String {
+        let limit = if limited { " LIMIT ?1" } else { "" };
+        format!("{}{}{}", self.select_all_sql(), order.sql(), limit)
+    }
+
+    /// Every column, whatever the table holds, in `order` and with the row
+    /// limit bound to `?1` when `limited`.
+    fn select_all_columns_sql(&self, order: &RowOrder, limited: bool) -> String {
+        let limit = if limited { " LIMIT ?1" } else { "" };
+        format!("SELECT * FROM {}{}{}", self.table, order.sql(), limit)
+    }
+
+    /// The query `select` runs for `format`.
+    fn select_sql(&self, format: OutputFormat, order: &RowOrder, limited: bool) -> String {
+        match format {
+            OutputFormat::Json => self.select_all_columns_sql(order, limited),
+            _ => self.select_users_sql(order, limited),
+        }
+    }
+
+    fn select_by_name_sql(&self) -> String {
+        format!("{} WHERE name = ?1", self.select_all_sql())
+    }
+
+    fn select_names_sql(&self) -> String {
+        format!("SELECT name FROM {}", self.table)
+    }
+
+    fn duplicate_names_sql(&self) -> String {
+        format!(
+            "SELECT name, COUNT(*) c FROM {} GROUP BY name HAVING c > 1",
+            self.table
+        )
+    }
+
+    fn count_sql(&self, filter: Option<&str>) -> String {
+        match filter {
+            Some(filter) => format!("SELECT COUNT(*) FROM {} WHERE {}", self.table, filter),
+            None => format!("SELECT COUNT(*) FROM {}", self.table),
+        }
+    }
+
+    fn rename_sql(&self) -> String {
+        format!("UPDATE {} SET name = ?1 WHERE id = ?2", self.table)
+    }
+
+    fn prefix_names_sql(&self) -> String {
+        format!("UPDATE {} SET name = ?1 || name", self.table)
+    }
+
+    fn prefix_name_by_id_sql(&self) -> String {
+        format!("{} WHERE id = ?2", self.prefix_names_sql())
+    }
+
+    fn select_ids_sql(&self) -> String {
+        format!("SELECT id FROM {}", self.table)
+    }
+
+    fn id_range_sql(&self) -> String {
+        format!("SELECT MIN(id), MAX(id) FROM {}", self.table)
+    }
+
+    /// Like `select_all_sql`, limited to the ids from `?1` to `?2` inclusive.
+    fn select_id_range_sql(&self) -> String {
+        format!("{} WHERE id BETWEEN ?1 AND ?2", self.select_all_sql())
+    }
+
+    fn delete_sql(&self, filter: Option<&str>) -> String {
+        match filter {
+            Some(filter) => format!("DELETE FROM {} WHERE {}", self.table, filter),
+            None => format!("DELETE FROM {}", self.table),
+        }
+    }
+
+    fn drop_table_sql(&self) -> String {
+        format!("DROP TABLE IF EXISTS {}", self.table)
+    }
+
+    /// The database the table lives in, `main` unless `--table` was
+    /// qualified, and the table's own name.
+    fn split_table(&self) -> (&str, &str) {
+        self.table.split_once('.').unwrap_or(("main", &self.table))
+    }
+
+    /// Checks whether the table exists, bound to its unqualified name as `?1`.
+    fn table_exists_sql(&self) -> String {
+        let (database, _) = self.split_table();
+        format!(
+            "SELECT EXISTS (SELECT 1 FROM {}.sqlite_schema WHERE type = 'table' AND name = ?1)",
+            database
+        )
+    }
+
+    fn create_index_sql(&self, column: &str) -> String {
+        // An index always lives next to its table, so only the index name
+        // takes the database prefix.
+        let (database, table) = self.split_table();
+        format!(
+            "CREATE INDEX IF NOT EXISTS {}.idx_{}_{} ON {} ({})",
+            database, table, column, table, column
+        )
+    }
+
+    /// How many rows fit in one INSERT without going over SQLite's limit on
+    /// bound parameters.
+    fn rows_per_stmt(&self, requested: usize) -> usize {
+        requested.min(MAX_BOUND_PARAMS / self.insert_width()).max(1)
+    }
+
+    /// The posts table, in the same database as the users since a foreign
+    /// key can't point into another one.
+    fn posts_table(&self) -> String {
+        let (database, _) = self.split_table();
+        format!("{}.{}", database, POSTS_TABLE)
+    }
+
+    /// Deleting or dropping a user takes their posts with them, so `delete`
+    /// and `reset` keep working once posts exist.
+    fn create_posts_table_sql(&self) -> String {
+        let (_, users) = self.split_table();
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n    \
+             id INTEGER PRIMARY KEY,\n    \
+             user_id INTEGER NOT NULL REFERENCES {}(id) ON DELETE CASCADE,\n    \
+             title TEXT NOT NULL\n)",
+            self.posts_table(),
+            users
+        )
+    }
+
+    /// Without it every deleted user would scan the whole posts table for
+    /// rows to cascade to.
+    fn create_posts_index_sql(&self) -> String {
+        let (database, _) = self.split_table();
+        format!(
+            "CREATE INDEX IF NOT EXISTS {}.idx_{}_user_id ON {} (user_id)",
+            database, POSTS_TABLE, POSTS_TABLE
+        )
+    }
+
+    /// Builds a posts INSERT with `rows` parenthesised value groups.
+    fn insert_posts_sql(&self, rows: usize) -> String {
+        let groups: Vec<String> = (0..rows)
+            .map(|row| format!("(?{}, ?{})", row * 2 + 1, row * 2 + 2))
+            .collect();
+
+        format!(
+            "INSERT INTO {} (user_id, title) VALUES {}",
+            self.posts_table(),
+            groups.join(",")
+        )
+    }
+
+    fn count_posts_sql(&self) -> String {
+        format!("SELECT COUNT(*) FROM {}", self.posts_table())
+    }
+
+    fn delete_posts_sql(&self) -> String {
+        format!("DELETE FROM {}", self.posts_table())
+    }
+}
+
+/// Name of the table `insert-posts` fills.
+const POSTS_TABLE: &str = "posts";
+
+/// WHERE clause used by `delete --id`, bound to the id as `?1`.
+const ID_FILTER: &str = "id = ?1";
+
+fn explain_sql(query: &str) -> String {
+    format!("EXPLAIN QUERY PLAN {}", query)
+}
+
+fn vacuum_sql(incremental: bool) -> &'static str {
+    if incremental {
+        "PRAGMA incremental_vacuum"
+    } else {
+        "VACUUM"
+    }
+}
+
+const CHECKPOINT_SQL: &str = "PRAGMA wal_checkpoint(TRUNCATE)";
+
+/// The destination is bound, so any path works without quoting.
+const VACUUM_INTO_SQL: &str = "VACUUM INTO ?1";
+
+/// Copies the whole WAL back like `CHECKPOINT_SQL` but leaves the file, so
+/// unlike a truncating checkpoint it still reports how many frames it copied.
+const FULL_CHECKPOINT_SQL: &str = "PRAGMA wal_checkpoint(FULL)";
+
+const INTEGRITY_CHECK_SQL: &str = "PRAGMA integrity_check";
+
+/// The pragmas `stats` reads, in the order it prints them.
+const STATS_PRAGMAS: [&str; 4] = ["page_count", "freelist_count", "page_size", "journal_mode"];
+
+/// The statements `command` runs, in order, without touching the database.
+/// Every string comes from the same builder the real execution uses.
+fn planned_sql(command: &Commands, schema: &Schema, create: bool) -> Vec<String> {
+    let mut sql = Vec::from_iter(create.then(|| schema.create_table_sql()));
+    match command {
+        Commands::Insert(insert) => {
+            let rows = schema.rows_per_stmt(insert.rows_per_stmt);
+            if insert.insert_mode == InsertMode::Truncate {
+                sql.push(schema.delete_sql(None));
+            }
+            sql.push(schema.insert_sql(insert.on_conflict, rows));
+            if insert.checkpoint_after {
+                sql.push(FULL_CHECKPOINT_SQL.to_owned());
+            }
+        }
+        Commands::Benchmark { insert, .. } => {
+            let rows = schema.rows_per_stmt(insert.rows_per_stmt);
+            sql.push(schema.delete_sql(None));
+            sql.push(schema.insert_sql(insert.on_conflict, rows));
+            if insert.checkpoint_after {
+                sql.push(FULL_CHECKPOINT_SQL.to_owned());
+            }
+        }
+        Commands::Select {
+            format,
+            limit,
+            sample,
+            order_by,
+            desc,
+            ..
+        } => {
+            let order = RowOrder::new(*sample, order_by.clone(), *desc);
+            sql.push(schema.select_sql(*format, &order, limit.is_some()));
+        }
+        Commands::ParallelSelect => {
+            sql.push(schema.id_range_sql());
+            sql.push(schema.select_id_range_sql());
+        }
+        Commands::Reset => sql = vec![schema.drop_table_sql(), schema.create_table_sql()],
+        Commands::Update {
+            set_prefix,
+            concurrent,
+            ..
+        } => match set_prefix {
+            Some(_) if *concurrent => {
+                sql.push(schema.select_ids_sql());
+                sql.push(schema.prefix_name_by_id_sql());
+            }
+            Some(_) => sql.push(schema.prefix_names_sql()),
+            None => sql.push(schema.rename_sql()),
+        },
+        Commands::Delete { filter, id, .. } => {
+            let filter = if id.is_some() {
+                Some(ID_FILTER)
+            } else {
+                filter.as_deref()
+            };
+            sql.push(schema.delete_sql(filter));
+        }
+        Commands::Vacuum { incremental } => {
+            sql.push(vacuum_sql(*incremental).to_owned());
+            sql.push(CHECKPOINT_SQL.to_owned());
+        }
+        Commands::Repl => {}
+        Commands::Backup { .. } => sql.push(VACUUM_INTO_SQL.to_owned()),
+        Commands::Checkpoint => {
+            sql.push(FULL_CHECKPOINT_SQL.to_owned());
+            sql.push(CHECKPOINT_SQL.to_owned());
+        }
+        Commands::Stats { .. } => {
+            sql.extend(STATS_PRAGMAS.map(|pragma| format!("PRAGMA {}", pragma)));
+        }
+        Commands::CreateIndex { column } => sql.push(schema.create_index_sql(column)),
+        Commands::Explain { query } => sql.push(explain_sql(query)),
+        Commands::Mix(mix) => {
+            let rows = schema.rows_per_stmt(mix.tx_size);
+            sql.push(schema.insert_sql(OnConflict::Fail, rows));
+            sql.push(if mix.count_only {
+                schema.count_sql(None)
+            } else {
+                schema.select_all_sql()
+            });
+            if mix.delete_after_secs.is_some() {
+                sql.push(schema.delete_sql(None));
+                sql.push(INTEGRITY_CHECK_SQL.to_owned());
+            }
+        }
+        Commands::Fuzz(_) => {
+            sql.push(schema.select_ids_sql());
+            sql.push(schema.insert_sql(OnConflict::Fail, 1));
+            sql.push(schema.select_by_name_sql());
+            sql.push(schema.rename_sql());
+            sql.push(schema.delete_sql(Some(ID_FILTER)));
+            sql.push(INTEGRITY_CHECK_SQL.to_owned());
+        }
+        Commands::VerifyUnique => sql.push(schema.duplicate_names_sql()),
+        Commands::Count { filter, .. } => sql.push(schema.count_sql(filter.as_deref())),
+        Commands::InsertPosts { user_id, .. } => {
+            sql.push(schema.create_posts_table_sql());
+            sql.push(schema.create_posts_index_sql());
+            if user_id.is_none() {
+                sql.push(schema.select_ids_sql());
+            }
+            sql.push(schema.insert_posts_sql(MAX_BOUND_PARAMS / 2));
+        }
+        Commands::Import { on_conflict, .. } => {
+            sql.push(schema.insert_sql(*on_conflict, schema.rows_per_stmt(MAX_BOUND_PARAMS)));
+        }
+        Commands::Lookup { count, .. } => {
+            if count.is_some() {
+                sql.push(schema.select_names_sql());
+            }
+            sql.push(schema.select_by_name_sql());
+        }
+    }
+
+    sql
+}
+
+/// Accepts plain SQL identifiers so table names can be spliced into queries.
+fn parse_identifier(s: &str) -> Result<String, String> {
+    let mut chars = s.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if valid {
+        Ok(s.to_owned())
+    } else {
+        Err(format!("`{}` is not a valid SQL identifier", s))
+    }
+}
+
+fn parse_page_size(s: &str) -> Result<u32, String> {
+    let size: u32 = s.parse().map_err(|e| format!("{}", e))?;
+    if !size.is_power_of_two() || !(512..=65536).contains(&size) {
+        return Err(format!(
+            "{} is not a power of two between 512 and 65536",
+            size
+        ));
+    }
+
+    Ok(size)
+}
+
+/// Accepts `table` or `database.table`, each part a plain identifier.
+fn parse_table_name(s: &str) -> Result<String, String> {
+    match s.split_once('.') {
+        Some((database, table)) => {
+            parse_identifier(database)?;
+            parse_identifier(table)?;
+        }
+        None => {
+            parse_identifier(s)?;
+        }
+    }
+
+    Ok(s.to_owned())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    #[error("failed to open database: {}", describe_call_error(.0))]
+    Open(#[source] tokio_rusqlite::Error),
+    #[error(
+        "{} appears to be locked by another process; still busy after {attempts} \
+         attempts to open it",
+        path.display()
+    )]
+    Locked {
+        path: PathBuf,
+        attempts: u32,
+        #[source]
+        source: tokio_rusqlite::Error,
+    },
+    #[error("failed to create schema: {}", describe_call_error(.0))]
+    Migration(#[source] tokio_rusqlite::Error),
+    #[error("insert failed: {}", describe_call_error(.0))]
+    Insert(#[source] tokio_rusqlite::Error),
+    #[error("name already exists: {}", describe_call_error(.0))]
+    UniqueViolation(#[source] tokio_rusqlite::Error),
+    #[error("referenced row does not exist: {}", describe_call_error(.0))]
+    ForeignKeyViolation(#[source] tokio_rusqlite::Error),
+    #[error("row rejected by a CHECK constraint: {}", describe_call_error(.0))]
+    ConstraintViolation(#[source] tokio_rusqlite::Error),
+    #[error("query failed: {}", describe_call_error(.0))]
+    Query(#[from] tokio_rusqlite::Error),
+    #[error(
+        "{} already has page_size={actual}, so --page-size {wanted} can't apply; \
+         it only affects new databases",
+        path.display()
+    )]
+    PageSize {
+        path: PathBuf,
+        wanted: u32,
+        actual: u32,
+    },
+    #[error("table `{0}` does not exist; run without --no-create")]
+    MissingTable(String),
+    #[error("table `{table}` has no column `{column}`")]
+    UnknownColumn { table: String, column: String },
+    #[error("invalid user: {0}")]
+    Validation(String),
+    #[error("failed to attach {} as {alias}: {}", path.display(), describe_call_error(source))]
+    Attach {
+        alias: String,
+        path: PathBuf,
+        #[source]
+        source: tokio_rusqlite::Error,
+    },
+    #[error("failed to load extension {}: {}", path.display(), describe_call_error(source))]
+    Extension {
+        path: PathBuf,
+        #[source]
+        source: tokio_rusqlite::Error,
+    },
+}
+
+impl DbError {
+    fn is_busy(&self) -> bool {
+        match self {
+            DbError::Open(e)
+            | DbError::Migration(e)
+            | DbError::Insert(e)
+            | DbError::UniqueViolation(e)
+            | DbError::ForeignKeyViolation(e)
+            | DbError::ConstraintViolation(e)
+            | DbError::Query(e) => is_busy(e),
+            DbError::Locked { .. } => true,
+            DbError::MissingTable(_)
+            | DbError::PageSize { .. }
+            | DbError::UnknownColumn { .. }
+            | DbError::Attach { .. }
+            | DbError::Extension { .. }
+            | DbError::Validation(_) => false,
+        }
+    }
+
+    /// Classifies an error from an insert, singling out UNIQUE, foreign key
+    /// and CHECK violations.
+    fn from_insert(err: tokio_rusqlite::Error) -> Self {
+        if is_unique_violation(&err) {
+            DbError::UniqueViolation(err)
+        } else if is_foreign_key_violation(&err) {
+            DbError::ForeignKeyViolation(err)
+        } else if is_check_violation(&err) {
+            DbError::ConstraintViolation(err)
+        } else {
+            DbError::Insert(err)
+        }
+    }
+}
+
+/// Row counts from one `insert_many` transaction. `inserted` is lower than
+/// `attempted` when conflicting rows were ignored, and `attempted` is lower
+/// than the batch size when the insert was cancelled part way.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InsertOutcome {
+    pub attempted: usize,
+    pub inserted: usize,
+    /// Rowid of the last row the transaction inserted, if it inserted any.
+    pub last_rowid: Option<i64>,
+    /// How many times the transaction was retried because the database was
+    /// busy.
+    pub retries: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum StatementKind {
+    Insert,
+    Select,
+    Update,
+    Delete,
+}
+
+impl StatementKind {
+    const ALL: [StatementKind; 4] = [
+        StatementKind::Insert,
+        StatementKind::Select,
+        StatementKind::Update,
+        StatementKind::Delete,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            StatementKind::Insert => "insert",
+            StatementKind::Select => "select",
+            StatementKind::Update => "update",
+            StatementKind::Delete => "delete",
+        }
+    }
+}
+
+/// Calls, rows and time spent per kind of statement, for `--profile` and
+/// `--status-addr`. The time is the whole `DB` call, so it includes waiting
+/// for the connection and for busy retries.
+#[derive(Debug)]
+struct Profile {
+    started: Instant,
+    kinds: [KindTotals; StatementKind::ALL.len()],
+}
+
+#[derive(Debug, Default)]
+struct KindTotals {
+    calls: AtomicU64,
+    rows: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Profile {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            kinds: Default::default(),
+        }
+    }
+
+    fn record(&self, kind: StatementKind, elapsed: Duration) {
+        let totals = &self.kinds[kind as usize];
+        totals.calls.fetch_add(1, Ordering::Relaxed);
+        totals
+            .nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Counts rows written, read, updated or deleted by calls of `kind`.
+    fn add_rows(&self, kind: StatementKind, rows: usize) {
+        self.kinds[kind as usize]
+            .rows
+            .fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Calls and rows so far per kind and the time since the run started.
+    fn to_json(&self) -> serde_json::Value {
+        let mut status = serde_json::Map::new();
+        status.insert(
+            "elapsed_secs".to_owned(),
+            self.started.elapsed().as_secs_f64().into(),
+        );
+        for kind in StatementKind::ALL {
+            let totals = &self.kinds[kind as usize];
+            let calls = totals.calls.load(Ordering::Relaxed);
+            let rows = totals.rows.load(Ordering::Relaxed);
+            status.insert(format!("{}_calls", kind.as_str()), calls.into());
+            status.insert(format!("{}_rows", kind.as_str()), rows.into());
+        }
+        status.into()
+    }
+
+    fn print(&self) {
+        println!(
+            "{:<9}  {:>10}  {:>10}  {:>12}  {:>12}",
+            "statement", "calls", "rows", "total", "mean"
+        );
+        for kind in StatementKind::ALL {
+            let totals = &self.kinds[kind as usize];
+            let calls = totals.calls.load(Ordering::Relaxed);
+            if calls == 0 {
+                continue;
+            }
+            let total = Duration::from_nanos(totals.nanos.load(Ordering::Relaxed));
+            println!(
+                "{:<9}  {:>10}  {:>10}  {:>12}  {:>12}",
+                kind.as_str(),
+                calls,
+                totals.rows.load(Ordering::Relaxed),
+                format!("{:.3?}", total),
+                format!("{:.3?}", total / calls as u32)
+            );
+        }
+    }
+}
+
+/// Records the time since it was started into its profile when dropped.
+struct StatementTimer {
+    profile: Arc<Profile>,
+    kind: StatementKind,
+    start: Instant,
+}
+
+impl Drop for StatementTimer {
+    fn drop(&mut self) {
+        self.profile.record(self.kind, self.start.elapsed());
+    }
+}
+
+/// What `DB::run_statement` got back.
+#[derive(Debug)]
+enum StatementResult {
+    Rows(Vec<serde_json::Value>),
+    Changed(usize),
+    Done,
+}
+
+/// What `PRAGMA wal_checkpoint` reported.
+#[derive(Clone, Copy, Debug)]
+struct Checkpoint {
+    /// Whether a reader or writer kept the checkpoint from finishing.
+    busy: bool,
+    /// Frames in the WAL, or -1 when the database isn't in WAL mode.
+    log_frames: i64,
+    /// Frames copied back into the database file, or -1 likewise.
+    checkpointed_frames: i64,
+}
+
+impl Checkpoint {
+    /// Reads the row a `wal_checkpoint` pragma returns.
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            busy: row.get(0)?,
+            log_frames: row.get(1)?,
+            checkpointed_frames: row.get(2)?,
+        })
+    }
+}
+
+/// What `stats` reports about the database.
+struct Stats {
+    page_count: i64,
+    freelist_count: i64,
+    page_size: i64,
+    journal_mode: String,
+    /// Size of the main database file, `None` for `:memory:`.
+    file_size: Option<u64>,
+}
+
+impl Stats {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "page_count": self.page_count,
+            "freelist_count": self.freelist_count,
+            "page_size": self.page_size,
+            "journal_mode": self.journal_mode,
+            "file_size": self.file_size,
+        })
+    }
+
+    fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.to_json());
+            return;
+        }
+
+        let file_size = match self.file_size {
+            Some(bytes) => format!("{} bytes", bytes),
+            None => "n/a (in memory)".to_owned(),
+        };
+        let rows = [
+            ("page_count", self.page_count.to_string()),
+            ("freelist_count", self.freelist_count.to_string()),
+            ("page_size", format!("{} bytes", self.page_size)),
+            ("journal_mode", self.journal_mode.clone()),
+            ("file_size", file_size),
+        ];
+        for (key, value) in rows {
+            println!("{:<14}  {}", key, value);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DB {
+    conn: Connection,
+    options: DbOptions,
+    schema: Arc<Schema>,
+    /// Where statement timings go with `--profile`, shared by the whole pool.
+    profile: Option<Arc<Profile>>,
+}
+
+impl DB {
+    pub async fn new(
+        path: &Path,
+        options: DbOptions,
+        schema: Arc<Schema>,
+    ) -> Result<Self, DbError> {
+        let in_memory = path == Path::new(MEMORY_DB);
+        let conn = if in_memory {
+            Connection::open_in_memory_with_flags(options.open_flags())
+                .await
+                .map_err(DbError::Open)?
+        } else {
+            Self::open_file(path, &options).await?
+        };
+
+        let setup = (path.to_owned(), options.clone());
+        conn.call(move |conn| {
+            let (path, options) = setup;
+            Ok(Self::configure(conn, &path, &options, in_memory))
+        })
+        .await
+        .map_err(DbError::Open)??;
+
+        Ok(Self {
+            conn,
+            options,
+            schema,
+            profile: None,
+        })
+    }
+
+    /// Applies `options` to a freshly opened connection: page size,
+    /// attachments, pragmas and extensions, in that order.
+    fn configure(
+        conn: &mut rusqlite::Connection,
+        path: &Path,
+        options: &DbOptions,
+        in_memory: bool,
+    ) -> Result<(), DbError> {
+        // Before anything else writes to the file, since the page size is
+        // fixed once the database has been created. Switching to WAL counts.
+        if let Some(wanted) = options.page_size {
+            conn.pragma_update(None, "page_size", wanted)
+                .map_err(|e| DbError::Open(e.into()))?;
+            let actual = conn
+                .pragma_query_value(None, "page_size", |row| row.get(0))
+                .map_err(|e| DbError::Open(e.into()))?;
+            if actual != wanted {
+                return Err(DbError::PageSize {
+                    path: path.to_owned(),
+                    wanted,
+                    actual,
+                });
+            }
+        }
+
+        // Attached first, so the journal_mode pragma below covers them too.
+        for attachment in &options.attachments {
+            // The alias was checked to be a plain identifier; the path is
+            // bound as a parameter.
+            conn.execute(
+                &format!("ATTACH DATABASE ?1 AS {}", attachment.alias),
+                [attachment.path.to_string_lossy()],
+            )
+            .map_err(|source| DbError::Attach {
+                alias: attachment.alias.clone(),
+                path: attachment.path.clone(),
+                source: source.into(),
+            })?;
+        }
+
+        let pragmas = || -> tokio_rusqlite::Result<()> {
+            conn.set_prepared_statement_cache_capacity(options.stmt_cache_capacity);
+            conn.pragma_update(None, "busy_timeout", options.busy_timeout_ms)?;
+            conn.pragma_update(None, "foreign_keys", true)?;
+            if let Some(synchronous) = options.synchronous {
+                conn.pragma_update(None, "synchronous", synchronous.as_str())?;
+            }
+            if let Some(bytes) = options.mmap_size {
+                conn.pragma_update(None, "mmap_size", bytes)?;
+            }
+            if let Some(size) = options.cache_size {
+                conn.pragma_update(None, "cache_size", size)?;
+            }
+            if let Some(pages) = options.wal_autocheckpoint {
+                conn.pragma_update(None, "wal_autocheckpoint", pages)?;
+            }
+            if options.no_checkpoint_on_close {
+                conn.set_db_config(DbConfig::SQLITE_DBCONFIG_NO_CKPT_ON_CLOSE, true)?;
+            }
+            if options.read_uncommitted {
+                conn.pragma_update(None, "read_uncommitted", true)?;
+            }
+
+            // In-memory databases always report the `memory` journal mode.
+            if !in_memory {
+                let wanted = options.journal_mode.as_str();
+                let mode: String =
+                    conn.pragma_update_and_check(None, "journal_mode", wanted, |row| row.get(0))?;
+                if !mode.eq_ignore_ascii_case(wanted) {
+                    return Err(tokio_rusqlite::Error::Other(
+                        format!("failed to set journal_mode to {}, got {}", wanted, mode).into(),
+                    ));
+                }
+            }
+
+            Ok(())
+        };
+        pragmas().map_err(DbError::Open)?;
+
+        for path in &options.extensions {
+            // SAFETY: an extension runs arbitrary native code inside this
+            // process, so loading is only enabled for the paths the user
+            // passed with --load-extension and switched off again after.
+            let loaded = unsafe {
+                conn.load_extension_enable().and_then(|()| {
+                    let loaded = conn.load_extension(path, None);
+                    conn.load_extension_disable()?;
+                    loaded
+                })
+            };
+            loaded.map_err(|source| DbError::Extension {
+                path: path.clone(),
+                source: source.into(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens a database file, retrying with backoff while another process
+    /// holds a lock on it. SQLite only touches the file on the first
+    /// statement, so each attempt reads the schema to find out.
+    async fn open_file(path: &Path, options: &DbOptions) -> Result<Connection, DbError> {
+        let busy_timeout_ms = options.busy_timeout_ms;
+        let mut attempt = 0;
+
+        loop {
+            let opened = async {
+                let conn = Connection::open_with_flags(path, options.open_flags()).await?;
+                conn.call(move |conn| {
+                    conn.pragma_update(None, "busy_timeout", busy_timeout_ms)?;
+                    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(()))?;
+                    Ok(())
+                })
+                .await?;
+                Ok(conn)
+            }
+            .await;
+            match opened {
+                Err(e) if is_busy(&e) && attempt < options.open_retries => {
+                    warn!("{} is locked, retrying the open", path.display());
+                    tokio::time::sleep(retry_delay(options, attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) if is_busy(&e) => {
+                    return Err(DbError::Locked {
+                        path: path.to_owned(),
+                        attempts: attempt + 1,
+                        source: e,
+                    })
+                }
+                result => return result.map_err(DbError::Open),
+            }
+        }
+    }
+
+    /// Starts timing a call of `kind`, recorded when the timer is dropped.
+    /// `None` unless `--profile` is on.
+    fn time(&self, kind: StatementKind) -> Option<StatementTimer> {
+        self.profile.as_ref().map(|profile| StatementTimer {
+            profile: Arc::clone(profile),
+            kind,
+            start: Instant::now(),
+        })
+    }
+
+    /// Adds `rows` to the profile's row count for `kind`, if there is one.
+    fn count_rows(&self, kind: StatementKind, rows: usize) {
+        if let Some(profile) = &self.profile {
+            profile.add_rows(kind, rows);
+        }
+    }
+
+    /// Reads back the effective `PRAGMA synchronous` level.
+    async fn synchronous(&self) -> Result<Synchronous, DbError> {
+        let level: i64 = self
+            .conn
+            .call(|conn| Ok(conn.pragma_query_value(None, "synchronous", |row| row.get(0))?))
+            .await?;
+        Synchronous::from_level(level).ok_or_else(|| {
+            DbError::Query(tokio_rusqlite::Error::Other(
+                format!("unknown synchronous level {}", level).into(),
+            ))
+        })
+    }
+
+    /// Reads back the effective `PRAGMA mmap_size`, in bytes.
+    async fn mmap_size(&self) -> Result<i64, DbError> {
+        Ok(self
+            .conn
+            .call(|conn| Ok(conn.pragma_query_value(None, "mmap_size", |row| row.get(0))?))
+            .await?)
+    }
+
+    /// Reads back the effective `PRAGMA cache_size`, in pages when positive
+    /// and KiB when negative.
+    async fn cache_size(&self) -> Result<i64, DbError> {
+        Ok(self
+            .conn
+            .call(|conn| Ok(conn.pragma_query_value(None, "cache_size", |row| row.get(0))?))
+            .await?)
+    }
+
+    /// Runs `function` on the connection, retrying with exponential backoff
+    /// while SQLite reports the database as busy or locked.
+    async fn call_with_retry<F, R>(&self, function: F) -> tokio_rusqlite::Result<R>
+    where
+        F: Fn(&mut rusqlite::Connection) -> tokio_rusqlite::Result<R> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        self.call_counting_retries(function)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    /// Like `call_with_retry`, also returning how many retries it took.
+    async fn call_counting_retries<F, R>(&self, function: F) -> tokio_rusqlite::Result<(R, u32)>
+    where
+        F: Fn(&mut rusqlite::Connection) -> tokio_rusqlite::Result<R> + Send + Sync + 'static,
+        R: Send + 'static,
+    {
+        let function = Arc::new(function);
+        let mut attempt = 0;
+
+        loop {
+            let f = Arc::clone(&function);
+            match self.conn.call(move |conn| f(conn)).await {
+                Err(e) if is_busy(&e) && attempt < self.options.max_retries => {
+                    tokio::time::sleep(retry_delay(&self.options, attempt)).await;
+                    attempt += 1;
+                }
+                result => return result.map(|result| (result, attempt)),
+            }
+        }
+    }
+
+    /// Checks that the table exists, for runs that shouldn't create it.
+    async fn ensure_table(&self) -> Result<(), DbError> {
+        let schema = Arc::clone(&self.schema);
+        let exists = self
+            .conn
+            .call(move |conn| {
+                Ok(conn.query_row(
+                    &schema.table_exists_sql(),
+                    [schema.split_table().1],
+                    |row| row.get(0),
+                )?)
+            })
+            .await?;
+        if exists {
+            Ok(())
+        } else {
+            Err(DbError::MissingTable(self.schema.table.clone()))
+        }
+    }
+
+    /// Creates the table or applies any migrations it is missing.
+    pub async fn migrate(&self) -> Result<(), DbError> {
+        let schema = Arc::clone(&self.schema);
+        let migrated = self
+            .conn
+            .call(move |conn| Ok(migrations::migrate(conn, &schema)?))
+            .await
+            .map_err(DbError::Migration)?;
+        match migrated {
+            Migrated::Created => info!("Table created"),
+            Migrated::Upgraded { from } => {
+                info!("Migrated table from v{} to v{}", from, migrations::LATEST)
+            }
+            Migrated::UpToDate => info!("Table exists"),
+        }
+
+        Ok(())
+    }
+
+    /// Drops the table and recreates it in one transaction. Returns whether
+    /// there was a table to drop.
+    async fn reset(&self) -> Result<bool, DbError> {
+        let schema = Arc::clone(&self.schema);
+        self.conn
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let existed = tx.query_row(
+                    &schema.table_exists_sql(),
+                    [schema.split_table().1],
+                    |row| row.get(0),
+                )?;
+                tx.execute(&schema.drop_table_sql(), ())?;
+                tx.execute(&schema.create_table_sql(), ())?;
+                migrations::set_version(&tx, &schema)?;
+                tx.commit()?;
+
+                Ok(existed)
+            })
+            .await
+            .map_err(DbError::Migration)
+    }
+
+    /// Inserts `users` in a single transaction, `rows_per_stmt` rows per
+    /// statement. Once `cancel` fires the remaining statements are skipped and
+    /// the rows so far are still committed.
+    pub async fn insert_many(
+        &self,
+        users: Vec<User>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> Result<InsertOutcome, DbError> {
+        let _timer = self.time(StatementKind::Insert);
+        for user in &users {
+            user.validate()?;
+        }
+        let schema = Arc::clone(&self.schema);
+        self.call_counting_retries(move |conn| {
+            Ok(insert_user_rows(conn, &schema, &users, options, &cancel)?)
+        })
+        .await
+        .map(|(outcome, retries)| {
+            self.count_rows(StatementKind::Insert, outcome.inserted);
+            InsertOutcome { retries, ..outcome }
+        })
+        .map_err(DbError::from_insert)
+    }
+
+    pub async fn select_all_users(&self) -> Result<Vec<DbUser>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.select_all_sql();
+        let rows = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt
+                    .query_map([], DbUser::from_row)?
+                    .collect::<Result<Vec<DbUser>, rusqlite::Error>>()?;
+
+                Ok(rows)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, rows.len());
+
+        Ok(rows)
+    }
+
+    /// Streams the users in `order`, at most `limit` of them, through a
+    /// bounded channel as the query produces them, so memory stays flat
+    /// regardless of table size. A query error is sent as the last item.
+    fn select_users(
+        &self,
+        order: &RowOrder,
+        limit: Option<usize>,
+    ) -> mpsc::Receiver<Result<DbUser, DbError>> {
+        self.stream_users(
+            self.schema.select_users_sql(order, limit.is_some()),
+            limit_param(limit),
+        )
+    }
+
+    fn stream_users(
+        &self,
+        sql: String,
+        params: Vec<rusqlite::types::Value>,
+    ) -> mpsc::Receiver<Result<DbUser, DbError>> {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let conn = self.conn.clone();
+        let timer = self.time(StatementKind::Select);
+        let profile = self.profile.clone();
+
+        tokio::spawn(async move {
+            let _timer = timer;
+            let rows_sender = sender.clone();
+            let result = conn
+                .call(move |conn| {
+                    let mut stmt = conn.prepare(&sql)?;
+                    let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+                    while let Some(row) = rows.next()? {
+                        let user = DbUser::from_row(row)?;
+                        // The receiver was dropped, nobody wants the rest.
+                        if rows_sender.blocking_send(Ok(user)).is_err() {
+                            break;
+                        }
+                        if let Some(profile) = &profile {
+                            profile.add_rows(StatementKind::Select, 1);
+                        }
+                    }
+
+                    Ok(())
+                })
+                .await;
+
+            if let Err(e) = result {
+                let _ = sender.send(Err(e.into())).await;
+            }
+        });
+
+        receiver
+    }
+
+    /// Reads every row `sql` returns into a JSON object keyed by column name,
+    /// so rows of any shape can be printed without a struct to hold them.
+    async fn select_rows_json(
+        &self,
+        sql: String,
+        params: Vec<rusqlite::types::Value>,
+    ) -> Result<Vec<serde_json::Value>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let rows = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                Ok(rows_to_json(&mut stmt, rusqlite::params_from_iter(params))?)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, rows.len());
+
+        Ok(rows)
+    }
+
+    /// Runs one statement of any kind: rows come back as JSON objects when it
+    /// returns any columns, otherwise the number of rows it changed if it is
+    /// an INSERT, UPDATE or DELETE.
+    async fn run_statement(&self, sql: String) -> Result<StatementResult, DbError> {
+        let result = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                if stmt.column_count() > 0 {
+                    return Ok(StatementResult::Rows(rows_to_json(&mut stmt, [])?));
+                }
+                let n_rows = stmt.execute([])?;
+                // SQLite only counts changes for these; after anything else
+                // the count is left over from an earlier statement.
+                let verb = sql.split_whitespace().next().unwrap_or_default();
+                let changes = ["INSERT", "UPDATE", "DELETE", "REPLACE"]
+                    .iter()
+                    .any(|dml| verb.eq_ignore_ascii_case(dml));
+                Ok(if changes {
+                    StatementResult::Changed(n_rows)
+                } else {
+                    StatementResult::Done
+                })
+            })
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn count_users(
+        &self,
+        filter: Option<String>,
+        params: Vec<String>,
+    ) -> Result<i64, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.count_sql(filter.as_deref());
+        let count = self
+            .conn
+            .call(move |conn| {
+                let count =
+                    conn.query_row(&sql, rusqlite::params_from_iter(params), |row| row.get(0))?;
+
+                Ok(count)
+            })
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn table_columns(&self) -> Result<Vec<String>, DbError> {
+        let (database, table) = self.schema.split_table();
+        let (database, table) = (database.to_owned(), table.to_owned());
+        let columns = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1, ?2)")?;
+                let columns = stmt
+                    .query_map([table, database], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+                Ok(columns)
+            })
+            .await?;
+
+        Ok(columns)
+    }
+
+    /// Whether the existing table has a UNIQUE index on `name` alone, whatever
+    /// `--no-unique` asked for.
+    async fn name_is_unique(&self) -> Result<bool, DbError> {
+        let (database, table) = self.schema.split_table();
+        let (database, table) = (database.to_owned(), table.to_owned());
+        let unique = self
+            .conn
+            .call(move |conn| {
+                Ok(conn.query_row(
+                    "SELECT EXISTS (
+                         SELECT 1 FROM pragma_index_list(?1, ?2) AS list
+                         WHERE list.\"unique\"
+                           AND (SELECT group_concat(name) FROM pragma_index_info(list.name, ?2)) = 'name'
+                     )",
+                    [table, database],
+                    |row| row.get(0),
+                )?)
+            })
+            .await?;
+
+        Ok(unique)
+    }
+
+    async fn create_index(&self, column: String) -> Result<(), DbError> {
+        if !self.table_columns().await?.contains(&column) {
+            return Err(DbError::UnknownColumn {
+                table: self.schema.table.clone(),
+                column,
+            });
+        }
+
+        let sql = self.schema.create_index_sql(&column);
+        self.conn
+            .call(move |conn| Ok(conn.execute(&sql, ())?))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the `EXPLAIN QUERY PLAN` rows for `query`, bound to `params`,
+    /// as (id, parent, detail) triples.
+    async fn explain(
+        &self,
+        query: String,
+        params: Vec<rusqlite::types::Value>,
+    ) -> Result<Vec<(i64, i64, String)>, DbError> {
+        let plan = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&explain_sql(&query))?;
+                let plan = stmt
+                    .query_map(rusqlite::params_from_iter(params), |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(3)?))
+                    })?
+                    .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+                Ok(plan)
+            })
+            .await?;
+
+        Ok(plan)
+    }
+
+    /// Writes a vacuumed copy of the database to `dest`, which must not exist
+    /// yet.
+    async fn vacuum_into(&self, dest: &Path) -> Result<(), DbError> {
+        let dest = dest.to_string_lossy().into_owned();
+        self.conn
+            .call(move |conn| Ok(conn.execute(VACUUM_INTO_SQL, [dest])?))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn vacuum(&self, incremental: bool) -> Result<(), DbError> {
+        self.conn
+            .call(move |conn| {
+                if incremental {
+                    // The pragma frees pages as it is stepped, so run it to
+                    // completion.
+                    let mut stmt = conn.prepare(vacuum_sql(true))?;
+                    let mut rows = stmt.query([])?;
+                    while rows.next()?.is_some() {}
+                } else {
+                    conn.execute(vacuum_sql(false), ())?;
+                }
+                // In WAL mode the rebuilt pages sit in the -wal file until they
+                // are checkpointed; elsewhere this is a no-op.
+                conn.query_row(CHECKPOINT_SQL, [], |_| Ok(()))?;
+
+                Ok(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reads the pragmas behind `stats`; the file size is left to the caller.
+    async fn stats(&self) -> Result<Stats, DbError> {
+        Ok(self
+            .conn
+            .call(|conn| {
+                let [page_count, freelist_count, page_size, journal_mode] = STATS_PRAGMAS;
+                Ok(Stats {
+                    page_count: conn.pragma_query_value(None, page_count, |row| row.get(0))?,
+                    freelist_count: conn
+                        .pragma_query_value(None, freelist_count, |row| row.get(0))?,
+                    page_size: conn.pragma_query_value(None, page_size, |row| row.get(0))?,
+                    journal_mode: conn.pragma_query_value(None, journal_mode, |row| row.get(0))?,
+                    file_size: None,
+                })
+            })
+            .await?)
+    }
+
+    /// Checkpoints the whole WAL and truncates it. Outside WAL mode SQLite
+    /// reports nothing to do.
+    async fn checkpoint(&self) -> Result<Checkpoint, DbError> {
+        let checkpoint = self
+            .call_with_retry(|conn| {
+                let full = conn.query_row(FULL_CHECKPOINT_SQL, [], Checkpoint::from_row)?;
+                // Everything was copied back already, so this only resets the
+                // file, unless a reader still needs it.
+                let busy: bool = conn.query_row(CHECKPOINT_SQL, [], |row| row.get(0))?;
+
+                Ok(Checkpoint {
+                    busy: full.busy || busy,
+                    ..full
+                })
+            })
+            .await?;
+
+        Ok(checkpoint)
+    }
+
+    /// Copies the whole WAL back into the database but leaves the file.
+    async fn full_checkpoint(&self) -> Result<Checkpoint, DbError> {
+        Ok(self
+            .call_with_retry(|conn| {
+                Ok(conn.query_row(FULL_CHECKPOINT_SQL, [], Checkpoint::from_row)?)
+            })
+            .await?)
+    }
+
+    async fn find_user(&self, name: String) -> Result<Option<DbUser>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.select_by_name_sql();
+        let name = self.schema.name_value(name);
+        let user = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(&sql)?;
+                let user = stmt.query_row([name], DbUser::from_row).optional()?;
+
+                Ok(user)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, usize::from(user.is_some()));
+
+        Ok(user)
+    }
+
+    /// Every name held by more than one row, with the number of rows holding
+    /// it.
+    async fn duplicate_names(&self) -> Result<Vec<(String, i64)>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.duplicate_names_sql();
+        let duplicates = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let duplicates = stmt
+                    .query_map([], |row| Ok((name_from_row(row, 0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(duplicates)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, duplicates.len());
+
+        Ok(duplicates)
+    }
+
+    async fn select_names(&self) -> Result<Vec<String>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.select_names_sql();
+        let names = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let names = stmt
+                    .query_map([], |row| name_from_row(row, 0))?
+                    .collect::<Result<Vec<String>, _>>()?;
+
+                Ok(names)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, names.len());
+
+        Ok(names)
+    }
+
+    async fn rename_user(&self, id: i64, name: String) -> Result<usize, DbError> {
+        let _timer = self.time(StatementKind::Update);
+        let sql = self.schema.rename_sql();
+        let name = self.schema.name_value(name);
+        let n_rows = self
+            .conn
+            .call(move |conn| Ok(conn.execute(&sql, rusqlite::params![name, id])?))
+            .await?;
+        self.count_rows(StatementKind::Update, n_rows);
+
+        Ok(n_rows)
+    }
+
+    /// Prepends `prefix` to every name in a single statement.
+    async fn prefix_names(&self, prefix: String) -> Result<usize, DbError> {
+        let _timer = self.time(StatementKind::Update);
+        let sql = self.schema.prefix_names_sql();
+        let n_rows = self
+            .conn
+            .call(move |conn| Ok(conn.execute(&sql, [prefix])?))
+            .await?;
+        self.count_rows(StatementKind::Update, n_rows);
+
+        Ok(n_rows)
+    }
+
+    /// Prepends `prefix` to the names of the given rows in one transaction,
+    /// retrying while another writer holds the lock. Once `cancel` fires the
+    /// remaining rows are skipped and the renames so far are still committed.
+    async fn prefix_names_by_id(
+        &self,
+        prefix: String,
+        ids: Vec<i64>,
+        cancel: CancellationToken,
+    ) -> Result<usize, DbError> {
+        let _timer = self.time(StatementKind::Update);
+        let sql = self.schema.prefix_name_by_id_sql();
+        let n_rows = self
+            .call_with_retry(move |conn| {
+                let tx = conn.transaction()?;
+                let mut n_rows = 0;
+                {
+                    let mut stmt = tx.prepare_cached(&sql)?;
+                    for id in &ids {
+                        if cancel.is_cancelled() {
+                            break;
+                        }
+                        n_rows += stmt.execute(rusqlite::params![prefix, id])?;
+                    }
+                }
+                tx.commit()?;
+
+                Ok(n_rows)
+            })
+            .await?;
+        self.count_rows(StatementKind::Update, n_rows);
+
+        Ok(n_rows)
+    }
+
+    async fn select_ids(&self) -> Result<Vec<i64>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.select_ids_sql();
+        let ids = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let ids = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<Vec<i64>, _>>()?;
+
+                Ok(ids)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, ids.len());
+
+        Ok(ids)
+    }
+
+    /// Returns the lowest and highest id in the table, or `None` when it is
+    /// empty.
+    async fn id_range(&self) -> Result<Option<(i64, i64)>, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.id_range_sql();
+        let range = self
+            .conn
+            .call(move |conn| {
+                let range =
+                    conn.query_row(&sql, [], |row| Ok(Option::zip(row.get(0)?, row.get(1)?)))?;
+
+                Ok(range)
+            })
+            .await?;
+
+        Ok(range)
+    }
+
+    /// Reads every user with an id from `lo` to `hi` inclusive and returns how
+    /// many there were. The rows are decoded like any select but not kept.
+    /// Stops early once `cancel` fires.
+    async fn read_id_range(
+        &self,
+        lo: i64,
+        hi: i64,
+        cancel: CancellationToken,
+    ) -> Result<usize, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.select_id_range_sql();
+        let n_rows = self
+            .conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let mut rows = stmt.query([lo, hi])?;
+                let mut n_rows = 0;
+                while let Some(row) = rows.next()? {
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+                    DbUser::from_row(row)?;
+                    n_rows += 1;
+                }
+
+                Ok(n_rows)
+            })
+            .await?;
+        self.count_rows(StatementKind::Select, n_rows);
+
+        Ok(n_rows)
+    }
+
+    /// Creates the posts table and its `user_id` index if they are missing.
+    async fn create_posts_table(&self) -> Result<(), DbError> {
+        let schema = Arc::clone(&self.schema);
+        self.conn
+            .call(move |conn| {
+                conn.execute(&schema.create_posts_table_sql(), ())?;
+                conn.execute(&schema.create_posts_index_sql(), ())?;
+
+                Ok(())
+            })
+            .await
+            .map_err(DbError::Migration)
+    }
+
+    /// Inserts `posts` in a single transaction, like `insert_many`. A post
+    /// whose user doesn't exist fails the whole batch with
+    /// `DbError::ForeignKeyViolation`.
+    async fn insert_posts(
+        &self,
+        posts: Vec<Post>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> Result<InsertOutcome, DbError> {
+        let _timer = self.time(StatementKind::Insert);
+        let schema = Arc::clone(&self.schema);
+        let rows_per_stmt = options.rows_per_stmt.clamp(1, MAX_BOUND_PARAMS / 2);
+        self.call_counting_retries(move |conn| {
+            let tx = conn.transaction_with_behavior(options.begin_mode.behavior())?;
+            let mut outcome = InsertOutcome::default();
+            for rows in posts.chunks(rows_per_stmt) {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let mut stmt = tx.prepare_cached(&schema.insert_posts_sql(rows.len()))?;
+                let values = rows
+                    .iter()
+                    .flat_map(|post| [&post.user_id as &dyn rusqlite::ToSql, &post.title]);
+                let inserted = stmt.execute(rusqlite::params_from_iter(values))?;
+                if inserted > 0 {
+                    outcome.last_rowid = Some(tx.last_insert_rowid());
+                }
+                outcome.inserted += inserted;
+                outcome.attempted += rows.len();
+            }
+            tx.commit()?;
+
+            Ok(outcome)
+        })
+        .await
+        .map(|(outcome, retries)| {
+            self.count_rows(StatementKind::Insert, outcome.inserted);
+            InsertOutcome { retries, ..outcome }
+        })
+        .map_err(DbError::from_insert)
+    }
+
+    async fn count_posts(&self) -> Result<i64, DbError> {
+        let _timer = self.time(StatementKind::Select);
+        let sql = self.schema.count_posts_sql();
+        let count = self
+            .conn
+            .call(move |conn| Ok(conn.query_row(&sql, [], |row| row.get(0))?))
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn delete_all_posts(&self) -> Result<(), DbError> {
+        let _timer = self.time(StatementKind::Delete);
+        let sql = self.schema.delete_posts_sql();
+        let n_rows = self
+            .call_with_retry(move |conn| Ok(conn.execute(&sql, ())?))
+            .await?;
+        self.count_rows(StatementKind::Delete, n_rows);
+        info!("Deleted {} posts", n_rows);
+
+        Ok(())
+    }
+
+    pub async fn delete_all_users(&self) -> Result<(), DbError> {
+        self.delete_users(None, Vec::new()).await?;
+        Ok(())
+    }
+
+    /// Deletes the rows matching `filter`, or every row when there is none,
+    /// retrying while another writer holds the lock. Returns how many rows
+    /// went.
+    async fn delete_users(
+        &self,
+        filter: Option<String>,
+        params: Vec<rusqlite::types::Value>,
+    ) -> Result<usize, DbError> {
+        let _timer = self.time(StatementKind::Delete);
+        let sql = self.schema.delete_sql(filter.as_deref());
+        let n_rows = self
+            .call_with_retry(move |conn| {
+                Ok(conn.execute(&sql, rusqlite::params_from_iter(params.iter()))?)
+            })
+            .await?;
+        self.count_rows(StatementKind::Delete, n_rows);
+        info!("Deleted {} rows", n_rows);
+
+        Ok(n_rows)
+    }
+
+    /// Runs `PRAGMA integrity_check`, returning the problems it reports, or
+    /// an empty list when the database is intact.
+    async fn integrity_check(&self) -> Result<Vec<String>, DbError> {
+        let problems = self
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare(INTEGRITY_CHECK_SQL)?;
+                let lines = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, _>>()?;
+
+                Ok(lines)
+            })
+            .await?;
+
+        Ok(problems.into_iter().filter(|line| line != "ok").collect())
+    }
+}
+
+/// Storage for one kind of entity, so the insertion workers and the benchmark
+/// aren't tied to the `users` table.
+pub trait Repository: Clone + Send + Sync + 'static {
+    /// What the workers insert.
+    type Entity: Clone + std::fmt::Debug + Send + Sync + 'static;
+
+    /// The value the table is unique on, used to work out how many rows a
+    /// batch should add.
+    fn unique_key(entity: &Self::Entity) -> &str;
+
+    /// Whether the table actually rejects duplicate `unique_key`s.
+    fn enforces_unique(&self) -> impl Future<Output = Result<bool, DbError>> + Send;
+
+    fn insert(
+        &self,
+        entities: Vec<Self::Entity>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> impl Future<Output = Result<InsertOutcome, DbError>> + Send;
+
+    fn count(&self) -> impl Future<Output = Result<i64, DbError>> + Send;
+
+    fn delete_all(&self) -> impl Future<Output = Result<(), DbError>> + Send;
+}
+
+impl Repository for DB {
+    type Entity = User;
+
+    fn unique_key(user: &User) -> &str {
+        &user.name
+    }
+
+    async fn insert(
+        &self,
+        users: Vec<User>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> Result<InsertOutcome, DbError> {
+        self.insert_many(users, options, cancel).await
+    }
+
+    async fn enforces_unique(&self) -> Result<bool, DbError> {
+        self.name_is_unique().await
+    }
+
+    async fn count(&self) -> Result<i64, DbError> {
+        self.count_users(None, Vec::new()).await
+    }
+
+    async fn delete_all(&self) -> Result<(), DbError> {
+        self.delete_all_users().await
+    }
+}
+
+/// The posts table behind the same connection as the users, so posts can go
+/// through the same insertion workers.
+#[derive(Clone)]
+struct Posts(DB);
+
+impl Repository for Posts {
+    type Entity = Post;
+
+    fn unique_key(post: &Post) -> &str {
+        &post.title
+    }
+
+    async fn enforces_unique(&self) -> Result<bool, DbError> {
+        Ok(false)
+    }
+
+    async fn insert(
+        &self,
+        posts: Vec<Post>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> Result<InsertOutcome, DbError> {
+        self.0.insert_posts(posts, options, cancel).await
+    }
+
+    async fn count(&self) -> Result<i64, DbError> {
+        self.0.count_posts().await
+    }
+
+    async fn delete_all(&self) -> Result<(), DbError> {
+        self.0.delete_all_posts().await
+    }
+}
+
+/// A plain rusqlite connection to the same file as `db`, for `--backend
+/// blocking`. Inserts run synchronously on tokio's blocking thread pool
+/// instead of tokio-rusqlite's per-connection thread; everything else goes
+/// through `db`.
+#[derive(Clone)]
+struct BlockingDb {
+    conn: Arc<std::sync::Mutex<rusqlite::Connection>>,
+    db: DB,
+}
+
+impl BlockingDb {
+    async fn open(db: DB, path: &Path) -> Result<Self, DbError> {
+        let path = path.to_owned();
+        let options = db.options.clone();
+        let conn = tokio::task::spawn_blocking(move || {
+            let mut conn = rusqlite::Connection::open_with_flags(&path, options.open_flags())
+                .map_err(|e| DbError::Open(e.into()))?;
+            DB::configure(&mut conn, &path, &options, false)?;
+            Ok::<_, DbError>(conn)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))?;
+
+        Ok(Self {
+            conn: Arc::new(std::sync::Mutex::new(conn)),
+            db,
+        })
+    }
+}
+
+impl Repository for BlockingDb {
+    type Entity = User;
+
+    fn unique_key(user: &User) -> &str {
+        &user.name
+    }
+
+    async fn enforces_unique(&self) -> Result<bool, DbError> {
+        self.db.name_is_unique().await
+    }
+
+    async fn insert(
+        &self,
+        users: Vec<User>,
+        options: InsertOptions,
+        cancel: CancellationToken,
+    ) -> Result<InsertOutcome, DbError> {
+        let _timer = self.db.time(StatementKind::Insert);
+        for user in &users {
+            user.validate()?;
+        }
+        let conn = Arc::clone(&self.conn);
+        let schema = Arc::clone(&self.db.schema);
+        let db_options = self.db.options.clone();
+        tokio::task::spawn_blocking(move || {
+            // Workers only share a connection when the pool is smaller than
+            // the worker count, and then they take turns like on one thread.
+            let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut attempt = 0;
+            loop {
+                let inserted = insert_user_rows(&mut conn, &schema, &users, options, &cancel)
+                    .map_err(tokio_rusqlite::Error::from);
+                match inserted {
+                    Err(e) if is_busy(&e) && attempt < db_options.max_retries => {
+                        std::thread::sleep(retry_delay(&db_options, attempt));
+                        attempt += 1;
+                    }
+                    result => {
+                        return result
+                            .map(|outcome| InsertOutcome {
+                                retries: attempt,
+                                ..outcome
+                            })
+                            .map_err(DbError::from_insert)
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+        .inspect(|outcome| self.db.count_rows(StatementKind::Insert, outcome.inserted))
+    }
+
+    async fn count(&self) -> Result<i64, DbError> {
+        self.db.count_users(None, Vec::new()).await
+    }
+
+    async fn delete_all(&self) -> Result<(), DbError> {
+        self.db.delete_all_users().await
+    }
+}
+
+/// A set of independent connections to the same database, handed out to
+/// workers round-robin.
+///
+/// Each `Connection` runs on its own background thread, so with WAL enabled
+/// readers on different connections really do run in parallel. Writers do not:
+/// SQLite holds a single write lock per database, and concurrent inserts wait
+/// for it (up to `--busy-timeout-ms`) instead of running side by side.
+#[derive(Clone)]
+pub struct DbPool<R = DB> {
+    dbs: Vec<R>,
+}
+
+impl DbPool {
+    /// Builds a pool of `size` connections, at most `max`, reusing `primary`
+    /// as the first and opening the rest with the same options and schema.
+    pub async fn open(primary: DB, path: &Path, size: usize, max: usize) -> Result<Self, DbError> {
+        if size > max {
+            warn!(
+                "Capping the pool at {} connections instead of {}; raise \
+                 --max-connections to open more",
+                max, size
+            );
+        }
+        let size = size.min(max);
+        let mut dbs = vec![primary];
+        for _ in 1..size {
+            // Every `:memory:` connection is a separate private database, so an
+            // in-memory pool has to share the primary connection.
+            let db = if path == Path::new(MEMORY_DB) {
+                dbs[0].clone()
+            } else {
+                let mut db =
+                    DB::new(path, dbs[0].options.clone(), Arc::clone(&dbs[0].schema)).await?;
+                db.profile = dbs[0].profile.clone();
+                db
+            };
+            dbs.push(db);
+        }
+
+        Ok(Self { dbs })
+    }
+
+    /// Gives every connection a `BlockingDb` twin on the same file.
+    async fn into_blocking(self, path: &Path) -> Result<DbPool<BlockingDb>, DbError> {
+        let mut dbs = Vec::with_capacity(self.dbs.len());
+        for db in self.dbs {
+            dbs.push(BlockingDb::open(db, path).await?);
+        }
+
+        Ok(DbPool { dbs })
+    }
+}
+
+impl<R: Clone> DbPool<R> {
+    pub fn get(&self, index: usize) -> R {
+        self.dbs[index % self.dbs.len()].clone()
+    }
+
+    /// Wraps every connection, e.g. to work on another table through it.
+    pub fn map<S>(self, f: impl FnMut(R) -> S) -> DbPool<S> {
+        DbPool {
+            dbs: self.dbs.into_iter().map(f).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbUser {
+    pub id: i64,
+    pub name: String,
+    pub email: String,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Payload>,
+}
+
+impl std::fmt::Display for DbUser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "id: {} name: {} email: {} created_at: {}",
+            self.id, self.name, self.email, self.created_at
+        )?;
+        if let Some(payload) = &self.payload {
+            write!(f, " payload: {} bytes", payload.0.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DbUser {
+    /// Reads a row selected as `id, name, email, created_at`, optionally
+    /// followed by `payload`, which is decompressed if it was stored
+    /// compressed.
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        let payload = if row.as_ref().column_count() > 4 {
+            row.get::<_, Option<Vec<u8>>>(4)?
+                .map(|bytes| {
+                    decode_payload(bytes).map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            4,
+                            rusqlite::types::Type::Blob,
+                            Box::new(e),
+                        )
+                    })
+                })
+                .transpose()?
+                .map(Payload)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            id: row.get(0)?,
+            name: name_from_row(row, 1)?,
+            email: row.get(2)?,
+            created_at: row.get(3)?,
+            payload,
+        })
+    }
+}
+
+/// A user's blob, printed as its length and serialized as base64 so a large
+/// one doesn't flood the output or the logs.
+#[derive(Clone)]
+pub struct Payload(pub Vec<u8>);
+
+impl std::fmt::Debug for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{} bytes>", self.0.len())
+    }
+}
+
+impl Serialize for Payload {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use base64::Engine;
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(&self.0))
+    }
+}
+
+/// Random alphanumeric bytes, so that `--compress` has something to gain
+/// without the data being trivially compressible.
+fn generate_payload(options: PayloadOptions, rng: &mut impl Rng) -> Vec<u8> {
+    let bytes: Vec<u8> = rng.sample_iter(&Alphanumeric).take(options.bytes).collect();
+    if options.compress {
+        zstd::encode_all(&bytes[..], 0).expect("compressing into memory can't fail")
+    } else {
+        bytes
+    }
+}
+
+/// Decompresses the base64 `payload` of a row read by `select_rows_json`.
+fn decode_json_payload(row: &mut serde_json::Value) -> anyhow::Result<()> {
+    use base64::Engine;
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    if let Some(serde_json::Value::String(encoded)) = row.get_mut("payload") {
+        let payload = decode_payload(engine.decode(&*encoded)?)?;
+        *encoded = engine.encode(payload);
+    }
+
+    Ok(())
+}
+
+/// Undoes `generate_payload`'s compression. A plain payload is alphanumeric,
+/// so it can never start with the zstd magic number.
+fn decode_payload(bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        zstd::decode_all(&bytes[..])
+    } else {
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub email: String,
+    /// Unix timestamp in seconds.
+    pub created_at: i64,
+    /// Values for the schema's extra columns, in order.
+    pub extra: Vec<String>,
+    /// The `payload` blob, already compressed if it is to be stored that way.
+    pub payload: Option<Payload>,
+}
+
+impl User {
+    pub fn new(
+        name: String,
+        email: String,
+        created_at: i64,
+        extra: Vec<String>,
+        payload: Option<Payload>,
+    ) -> Self {
+        Self {
+            name,
+            email,
+            created_at,
+            extra,
+            payload,
+        }
+    }
+
+    /// Catches rows SQLite would accept but that make no sense as users.
+    fn validate(&self) -> Result<(), DbError> {
+        if self.name.is_empty() {
+            return Err(DbError::Validation("name is empty".into()));
+        }
+        if !self.email.contains('@') {
+            return Err(DbError::Validation(format!(
+                "email {:?} of {} has no @",
+                self.email, self.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// The values bound for this user's row, each paired with its column in
+    /// `schema`. The name is bound as text or as a blob per `--name-type`.
+    fn named_values<'a>(
+        &'a self,
+        schema: &'a Schema,
+    ) -> impl Iterator<Item = (&'a str, ToSqlOutput<'a>)> {
+        let text = |value: &'a str| ToSqlOutput::Borrowed(ValueRef::Text(value.as_bytes()));
+        let name = match schema.name_type {
+            NameType::Text => text(&self.name),
+            NameType::Blob => ToSqlOutput::Borrowed(ValueRef::Blob(self.name.as_bytes())),
+        };
+        [
+            ("name", name),
+            ("email", text(&self.email)),
+            ("created_at", ToSqlOutput::from(self.created_at)),
+        ]
+        .into_iter()
+        .chain(
+            schema
+                .extra_columns
+                .iter()
+                .map(String::as_str)
+                .zip(self.extra.iter().map(move |value| text(value))),
+        )
+        .chain(
+            self.payload
+                .iter()
+                .map(|value| ("payload", ToSqlOutput::Borrowed(ValueRef::Blob(&value.0)))),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Post {
+    user_id: i64,
+    title: String,
+}
+
+/// `per_user` posts with random titles for each of `user_ids`.
+fn create_posts(user_ids: &[i64], per_user: usize, rng: &mut impl Rng) -> Vec<Post> {
+    user_ids
+        .iter()
+        .flat_map(|&user_id| std::iter::repeat_n(user_id, per_user))
+        .map(|user_id| Post {
+            user_id,
+            title: generate_name(NAME_LEN, rng),
+        })
+        .collect()
+}
+
+/// Row counts and timing reported by a single insertion worker.
+#[derive(Debug)]
+pub struct WorkerStats {
+    pub name: String,
+    pub inserted: usize,
+    pub ignored: usize,
+    pub failed: usize,
+    /// Inserts that succeeded after at least one busy retry.
+    pub retried: usize,
+    /// Inserts still busy after the last retry, counted in `failed` too.
+    pub exhausted: usize,
+    pub elapsed: Duration,
+    /// Per-transaction insert latency in microseconds, only collected with
+    /// `--latency`.
+    pub latency: Option<Histogram<u64>>,
+}
+
+impl WorkerStats {
+    fn new(name: String, latency: bool) -> Self {
+        Self {
+            name,
+            inserted: 0,
+            ignored: 0,
+            failed: 0,
+            retried: 0,
+            exhausted: 0,
+            elapsed: Duration::ZERO,
+            latency: latency.then(new_histogram),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "inserted": self.inserted,
+            "ignored": self.ignored,
+            "failed": self.failed,
+            "retried": self.retried,
+            "exhausted_retries": self.exhausted,
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+        })
+    }
+}
+
+/// The outcome of one `run_insertion` call.
+#[derive(Debug)]
+pub struct InsertionReport {
+    workers: Vec<WorkerStats>,
+    elapsed: Duration,
+    /// Number of rows the workers were expected to add: the distinct names
+    /// handed to them, or every name when the table isn't unique on them.
+    expected_rows: usize,
+    /// How much the table grew during the run.
+    actual_rows: usize,
+    on_conflict: OnConflict,
+    backend: Backend,
+    begin_mode: BeginMode,
+    /// Most rows a worker committed per transaction.
+    tx_size: usize,
+    /// How long the `--checkpoint-after` checkpoint took, and what it did.
+    checkpoint: Option<(Duration, Checkpoint)>,
+    /// The file sizes `--sample-interval-ms` recorded.
+    growth: Option<Vec<SizeSample>>,
+    /// The aggregate `--target-rate`, in rows per second.
+    target_rate: Option<u64>,
+}
+
+impl InsertionReport {
+    /// What each worker did, in the order the workers were started.
+    pub fn workers(&self) -> &[WorkerStats] {
+        &self.workers
+    }
+
+    pub fn inserted(&self) -> usize {
+        self.workers.iter().map(|w| w.inserted).sum()
+    }
+
+    pub fn ignored(&self) -> usize {
+        self.workers.iter().map(|w| w.ignored).sum()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.workers.iter().map(|w| w.failed).sum()
+    }
+
+    fn retried(&self) -> usize {
+        self.workers.iter().map(|w| w.retried).sum()
+    }
+
+    fn exhausted(&self) -> usize {
+        self.workers.iter().map(|w| w.exhausted).sum()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn rows_per_sec(&self) -> f64 {
+        self.inserted() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Share of the rows that reached the database but hit an existing name.
+    fn collision_rate(&self) -> f64 {
+        let attempted = self.inserted() + self.ignored();
+        if attempted == 0 {
+            0.0
+        } else {
+            self.ignored() as f64 / attempted as f64
+        }
+    }
+
+    /// All workers' transaction latencies merged into one histogram, if they
+    /// were collected.
+    fn latency(&self) -> Option<Histogram<u64>> {
+        let mut merged: Option<Histogram<u64>> = None;
+        for histogram in self.workers.iter().filter_map(|w| w.latency.as_ref()) {
+            merged
+                .get_or_insert_with(new_histogram)
+                .add(histogram)
+                .expect("auto-resizing histograms can always be merged");
+        }
+        merged
+    }
+
+    /// Fails when more inserts ran out of retries than `limit` allows.
+    fn check_retries(&self, limit: Option<usize>) -> Result<(), RetriesExhausted> {
+        match limit {
+            Some(limit) if self.exhausted() > limit => Err(RetriesExhausted {
+                exhausted: self.exhausted(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the run stayed well below its `--target-rate`.
+    fn fell_short(&self) -> bool {
+        self.target_rate
+            .is_some_and(|target| self.rows_per_sec() < target as f64 * TARGET_RATE_SLACK)
+    }
+
+    /// Whether every name landed in the table. With `--on-conflict ignore` or
+    /// `replace` names that already existed don't add rows, so a shortfall is
+    /// expected there.
+    pub fn verified(&self) -> bool {
+        self.actual_rows == self.expected_rows || self.on_conflict != OnConflict::Fail
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "inserted": self.inserted(),
+            "ignored": self.ignored(),
+            "failed": self.failed(),
+            "retried": self.retried(),
+            "exhausted_retries": self.exhausted(),
+            "collision_rate": self.collision_rate(),
+            "expected_rows": self.expected_rows,
+            "actual_rows": self.actual_rows,
+            "verified": self.verified(),
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+            "rows_per_sec": self.rows_per_sec(),
+            "backend": self.backend.as_str(),
+            "begin_mode": self.begin_mode.as_str(),
+            "tx_size": self.tx_size,
+            "target_rate": self.target_rate,
+            "fell_short_of_target": self.target_rate.map(|_| self.fell_short()),
+            "checkpoint_secs": self.checkpoint.as_ref().map(|(elapsed, _)| elapsed.as_secs_f64()),
+            "file_growth": self.growth,
+            "tx_latency_us": self.latency().map(|h| serde_json::json!({
+                "p50": h.value_at_quantile(0.5),
+                "p90": h.value_at_quantile(0.9),
+                "p99": h.value_at_quantile(0.99),
+                "max": h.max(),
+            })),
+            "workers": self.workers.iter().map(WorkerStats::to_json).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", self.to_json());
+            return;
+        }
+
+        for worker in &self.workers {
+            println!(
+                "{} inserted {} rows, ignored {}, {} retried, {} out of retries in {:.3?}",
+                worker.name,
+                worker.inserted,
+                worker.ignored,
+                worker.retried,
+                worker.exhausted,
+                worker.elapsed
+            );
+        }
+        println!(
+            "Inserted {} rows, ignored {}, failed {} in {:.3?} ({:.0} rows/s, {} backend, BEGIN {})",
+            self.inserted(),
+            self.ignored(),
+            self.failed(),
+            self.elapsed,
+            self.rows_per_sec(),
+            self.backend.as_str(),
+            self.begin_mode.as_str().to_uppercase()
+        );
+        if let Some(target) = self.target_rate.filter(|_| self.fell_short()) {
+            println!(
+                "Fell short of the target rate of {} rows/s; the database or the workers can't keep up",
+                target
+            );
+        }
+        if let Some((elapsed, checkpoint)) = &self.checkpoint {
+            println!(
+                "Checkpointed {} of {} WAL frames in {:.3?} after the inserts",
+                checkpoint.checkpointed_frames.max(0),
+                checkpoint.log_frames.max(0),
+                elapsed
+            );
+        }
+        if self.retried() + self.exhausted() > 0 {
+            println!(
+                "{} inserts succeeded after a busy retry, {} ran out of retries",
+                self.retried(),
+                self.exhausted()
+            );
+        }
+        if self.collision_rate() > COLLISION_WARN_RATE {
+            println!(
+                "{:.1}% of inserts collided with an existing name; a larger --name-len makes that rarer",
+                self.collision_rate() * 100.0
+            );
+        }
+        if let Some(h) = self.latency() {
+            let us = Duration::from_micros;
+            println!(
+                "Transaction latency over {} transactions: p50 {:.3?}, p90 {:.3?}, p99 {:.3?}, max {:.3?}",
+                h.len(),
+                us(h.value_at_quantile(0.5)),
+                us(h.value_at_quantile(0.9)),
+                us(h.value_at_quantile(0.99)),
+                us(h.max())
+            );
+        }
+        println!(
+            "Expected {} new rows, table grew by {}",
+            self.expected_rows, self.actual_rows
+        );
+        if self.actual_rows != self.expected_rows && self.verified() {
+            println!(
+                "The difference is explained by --on-conflict {}",
+                self.on_conflict.to_possible_value().unwrap().get_name()
+            );
+        }
+        if let Some(growth) = &self.growth {
+            println!("elapsed_ms,bytes");
+            for sample in growth {
+                println!("{},{}", sample.elapsed_ms, sample.bytes);
+            }
+        }
+    }
+}
+
+/// Operation counts for one role in the mixed workload.
+#[derive(Debug, Default)]
+struct RoleStats {
+    ops: usize,
+    rows: usize,
+    busy: usize,
+    errors: usize,
+}
+
+impl RoleStats {
+    fn merge(&mut self, other: RoleStats) {
+        self.ops += other.ops;
+        self.rows += other.rows;
+        self.busy += other.busy;
+        self.errors += other.errors;
+    }
+
+    fn record_error(&mut self, err: &DbError) {
+        if err.is_busy() {
+            self.busy += 1;
+        } else {
+            self.errors += 1;
+        }
+    }
+}
+
+pub fn create_users(count: usize, shape: UserShape, rng: &mut impl Rng) -> Vec<User> {
+    (0..count)
+        .map(|_| {
+            let name = generate_name(shape.name_len, rng);
+            fill_user(name, shape, rng)
+        })
+        .collect()
+}
+
+/// Completes a user around `name` with a matching email, a random creation
+/// time, random extra columns and a random payload if `shape` has one.
+fn fill_user(name: String, shape: UserShape, rng: &mut impl Rng) -> User {
+    let email = generate_email(&name);
+    let created_at = generate_created_at(rng);
+    let extra = (0..shape.extra_cols)
+        .map(|_| generate_name(NAME_LEN, rng))
+        .collect();
+    let payload = shape
+        .payload
+        .map(|options| Payload(generate_payload(options, rng)));
+    User::new(name, email, created_at, extra, payload)
+}
+
+/// Builds the workload for an insert run: users read from `--from-file`,
+/// generated on the fly for `--duration-secs`, or generated up front.
+fn workload_for(
+    args: &InsertArgs,
+    shape: UserShape,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Workload<User>> {
+    if let Some(secs) = args.duration_secs {
+        return Ok(Workload::Timed {
+            duration: Duration::from_secs(secs),
+            seed: rng.gen(),
+            generate: Arc::new(move |count, rng| create_users(count, shape, rng)),
+        });
+    }
+
+    let users = match &args.from_file {
+        Some(path) => load_users(path, shape, rng)?,
+        None => create_users(args.count, shape, rng),
+    };
+    Ok(Workload::Fixed(users))
+}
+
+/// Reads one name per line, trimming whitespace and skipping blank lines.
+/// The other columns are still generated.
+fn load_users(path: &Path, shape: UserShape, rng: &mut impl Rng) -> anyhow::Result<Vec<User>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read names from {}", path.display()))?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|name| fill_user(name.to_owned(), shape, rng))
+        .collect())
+}
+
+/// Reads users from a CSV file whose header has a `name` column and
+/// optionally `email` and `created_at`. Other columns, `id` included, are
+/// ignored, and whatever is missing is generated. Returns the users along with
+/// the number of malformed lines skipped; without `skip_bad` the first one is
+/// an error.
+fn load_csv(
+    path: &Path,
+    shape: UserShape,
+    skip_bad: bool,
+    rng: &mut impl Rng,
+) -> anyhow::Result<(Vec<User>, usize)> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.trim() == name);
+    let name_column = column("name")
+        .ok_or_else(|| anyhow!("{} has no `name` column in its header", path.display()))?;
+    let (email_column, created_at_column) = (column("email"), column("created_at"));
+
+    let mut users = Vec::new();
+    let mut skipped = 0;
+    for record in reader.records() {
+        let line = match &record {
+            Ok(record) => record.position(),
+            Err(e) => e.position(),
+        }
+        .map_or(0, csv::Position::line);
+        let parsed = record.map_err(|e| e.to_string()).and_then(|record| {
+            let name = record.get(name_column).unwrap_or_default().trim();
+            let mut user = fill_user(name.to_owned(), shape, rng);
+            if let Some(email) = email_column.and_then(|i| record.get(i)) {
+                user.email = email.trim().to_owned();
+            }
+            if let Some(created_at) = created_at_column.and_then(|i| record.get(i)) {
+                user.created_at = created_at
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("created_at `{}` is not an integer", created_at))?;
+            }
+            user.validate().map_err(|e| e.to_string())?;
+            Ok(user)
+        });
+
+        match parsed {
+            Ok(user) => users.push(user),
+            Err(e) => {
+                if !skip_bad {
+                    return Err(anyhow!("{}:{}: {}", path.display(), line, e));
+                }
+                warn!("Skipping {}:{}: {}", path.display(), line, e);
+                skipped += 1;
+            }
+        }
+    }
+
+    Ok((users, skipped))
+}
+
+/// Steps `stmt` to the end, turning every row into a JSON object keyed by
+/// column name.
+fn rows_to_json(
+    stmt: &mut rusqlite::Statement<'_>,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<serde_json::Value>> {
+    let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    let mut rows = stmt.query(params)?;
+    let mut objects = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            object.insert(column.clone(), value_to_json(row.get_ref(i)?));
+        }
+        objects.push(serde_json::Value::Object(object));
+    }
+
+    Ok(objects)
+}
+
+/// Reads a `name`, stored as TEXT or, with `--name-type blob`, as a BLOB of
+/// its UTF-8 bytes.
+fn name_from_row(row: &rusqlite::Row<'_>, idx: usize) -> rusqlite::Result<String> {
+    match row.get_ref(idx)? {
+        ValueRef::Blob(bytes) => String::from_utf8(bytes.to_vec()).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Blob, Box::new(e))
+        }),
+        _ => row.get(idx),
+    }
+}
+
+/// Maps an SQLite value to JSON: integers and reals become numbers, text a
+/// string, a blob a base64 string and NULL null. Reals JSON can't represent,
+/// the infinities, become null too.
+fn value_to_json(value: rusqlite::types::ValueRef<'_>) -> serde_json::Value {
+    use base64::Engine;
+
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => {
+            serde_json::Number::from_f64(f).map_or(serde_json::Value::Null, Into::into)
+        }
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+        ValueRef::Blob(blob) => base64::engine::general_purpose::STANDARD
+            .encode(blob)
+            .into(),
+    }
+}
+
+/// Returns the RNG for generating users along with the seed it was built from.
+fn seeded_rng(seed: Option<u64>) -> (StdRng, u64) {
+    let seed = seed.unwrap_or_else(|| thread_rng().gen());
+    (StdRng::seed_from_u64(seed), seed)
+}
+
+pub fn generate_name(len: usize, rng: &mut impl Rng) -> String {
+    rng.sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn generate_email(name: &str) -> String {
+    format!("{}@example.com", name.to_lowercase())
+}
+
+/// A time in the year after `CREATED_AT_BASE`. The range is fixed rather than
+/// relative to now so a `--seed` always produces the same rows.
+fn generate_created_at(rng: &mut impl Rng) -> i64 {
+    CREATED_AT_BASE + rng.gen_range(0..365 * 24 * 60 * 60)
+}
+
+/// Progress bars are drawn through this, and log lines go through it too so a
+/// line is never written over a half-drawn bar.
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Writes log lines to stderr with any progress bar hidden for the duration.
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        PROGRESS.suspend(|| std::io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// A rows done / total bar with an ETA, or just a running count when the total
+/// isn't known. `None` when `--quiet` is set or stdout isn't a terminal.
+fn progress_bar(total: Option<u64>, quiet: bool) -> Option<ProgressBar> {
+    if quiet || !std::io::stdout().is_terminal() {
+        return None;
+    }
+
+    let (bar, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total),
+            "{bar:40} {pos}/{len} rows ({per_sec}, ETA {eta})",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{spinner} {pos} rows ({per_sec}, {elapsed})",
+        ),
+    };
+    let bar = PROGRESS.add(bar);
+    bar.set_style(ProgressStyle::with_template(template).expect("progress template is valid"));
+    Some(bar)
+}
+
+/// Sends logs to stderr so stdout only carries command output. The filter comes
+/// from `--log-level`, then `RUST_LOG`, and defaults to `info`.
+fn init_logging(level: Option<&str>) -> anyhow::Result<()> {
+    let filter = match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| LogWriter)
+        .init();
+
+    Ok(())
+}
+
+/// Resolves a relative database path against the current working directory,
+/// leaving `:memory:` untouched.
+fn resolve_db_path(path: &Path) -> anyhow::Result<PathBuf> {
+    if path == Path::new(MEMORY_DB) || path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    Ok(std::env::current_dir()?.join(path))
+}
+
+/// Returns the size of the main database file, or `None` for `:memory:`.
+fn db_file_size(path: &Path) -> std::io::Result<Option<u64>> {
+    if path == Path::new(MEMORY_DB) {
+        return Ok(None);
+    }
+
+    Ok(Some(std::fs::metadata(path)?.len()))
+}
+
+/// Returns the size of the database's `-wal` file, 0 when there is none, or
+/// `None` for `:memory:`.
+fn wal_file_size(path: &Path) -> std::io::Result<Option<u64>> {
+    if path == Path::new(MEMORY_DB) {
+        return Ok(None);
+    }
+
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    match std::fs::metadata(wal) {
+        Ok(metadata) => Ok(Some(metadata.len())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Some(0)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returned when a run completed but some of its inserts didn't.
+#[derive(Debug, thiserror::Error)]
+#[error("{failed} inserts failed")]
+struct PartialFailure {
+    failed: usize,
+}
+
+/// Returned when more inserts ran out of busy retries than
+/// `--fail-after-retries` allows.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "{exhausted} inserts ran out of retries, more than --fail-after-retries {limit}; \
+     the database can't keep up with this many writers"
+)]
+struct RetriesExhausted {
+    exhausted: usize,
+    limit: usize,
+}
+
+/// Returned when Ctrl-C stopped a run after its in-flight work committed.
+#[derive(Debug, thiserror::Error)]
+#[error("interrupted after {progress}")]
+struct Interrupted {
+    /// What the run got done, such as "inserting 10 rows".
+    progress: String,
+}
+
+/// The error for a run the cancellation token stopped early: a timeout when
+/// `--timeout-secs` fired it, otherwise Ctrl-C.
+fn stopped_early(timed_out: &AtomicBool, progress: String) -> anyhow::Error {
+    if timed_out.load(Ordering::Relaxed) {
+        anyhow!("timed out after {}", progress)
+    } else {
+        Interrupted { progress }.into()
+    }
+}
+
+/// Maps an error from `run` to the process exit code documented in the readme.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if err.downcast_ref::<Interrupted>().is_some() {
+        return EXIT_INTERRUPTED;
+    }
+    if err.downcast_ref::<PartialFailure>().is_some() {
+        return EXIT_PARTIAL_FAILURE;
+    }
+    if err.downcast_ref::<RetriesExhausted>().is_some() {
+        return EXIT_DB_UNAVAILABLE;
+    }
+    match err.downcast_ref::<DbError>() {
+        Some(DbError::Open(_) | DbError::Locked { .. } | DbError::Attach { .. }) => {
+            EXIT_DB_UNAVAILABLE
+        }
+        Some(e) if e.is_busy() => EXIT_DB_UNAVAILABLE,
+        _ => EXIT_ERROR,
+    }
+}
+
+/// Parses the command line and runs the command it names, the whole
+/// `mult_sql` binary short of turning errors into an exit code.
+pub async fn run() -> anyhow::Result<()> {
+    let matches = match Args::command().try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => {
+            // clap exits with 2 on usage errors, which would read as a
+            // partial failure here.
+            let _ = e.print();
+            std::process::exit(if e.use_stderr() { EXIT_ERROR } else { 0 });
+        }
+    };
+    let mut cli = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_logging(cli.log_level.as_deref())?;
+    if let Some(path) = cli.config.clone() {
+        Config::load(&path)?.apply(&mut cli, &matches);
+    }
+
+    let db_path = resolve_db_path(&cli.db_path)?;
+    let shape = cli.schema.user_shape();
+    let schema = Arc::new(Schema::new(
+        cli.schema.table,
+        cli.schema.extra_cols,
+        !cli.schema.no_unique,
+        cli.schema.max_name_len,
+        shape.payload.is_some(),
+        cli.schema.name_type,
+    ));
+    if cli.dry_run {
+        for sql in planned_sql(&cli.command, &schema, !cli.no_create) {
+            println!("{};", sql);
+        }
+        return Ok(());
+    }
+    let mut db = DB::new(&db_path, cli.db_options, schema).await?;
+    let mmap_size = db.mmap_size().await?;
+    info!(
+        "Opened {} with synchronous={}, cache_size={}, mmap_size={}, stmt_cache_capacity={}",
+        db_path.display(),
+        db.synchronous().await?.as_str(),
+        db.cache_size().await?,
+        mmap_size,
+        db.options.stmt_cache_capacity
+    );
+    if let Some(requested) = db.options.mmap_size {
+        if u64::try_from(mmap_size).ok() != Some(requested) {
+            warn!(
+                "Requested mmap_size={} but SQLite capped it at {}",
+                requested, mmap_size
+            );
+        }
+    }
+    // Reset creates the table itself, after reporting whether it existed.
+    if !matches!(cli.command, Commands::Reset) {
+        if cli.no_create {
+            db.ensure_table().await?;
+        } else {
+            db.migrate().await?;
+        }
+
+        if db.schema.payload && !db.table_columns().await?.iter().any(|c| c == "payload") {
+            return Err(anyhow!(
+                "{} has no payload column; --with-payload only adds it to tables it \
+                 creates, so reset the table first",
+                db.schema.table
+            ));
+        }
+
+        let unique = db.name_is_unique().await?;
+        info!(
+            "{}.name is {}",
+            db.schema.table,
+            if unique { "UNIQUE" } else { "not unique" }
+        );
+        if unique == cli.schema.no_unique {
+            warn!(
+                "{} already existed {} a UNIQUE name; --no-unique only affects tables it creates",
+                db.schema.table,
+                if unique { "with" } else { "without" }
+            );
+        }
+    }
+
+    // Set up only now, so the checks above don't show up in it.
+    let profile = (cli.profile || cli.status_addr.is_some()).then(|| Arc::new(Profile::new()));
+    db.profile = profile.clone();
+    if let (Some(addr), Some(profile)) = (cli.status_addr, &profile) {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to listen on {}", addr))?;
+        info!("Serving status on http://{}", listener.local_addr()?);
+        tokio::spawn(serve_status(listener, Arc::clone(profile)));
+    }
+
+    let cancel = CancellationToken::new();
+    if cli.command.stops_on_ctrl_c() {
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    warn!("Interrupted, committing in-flight transactions");
+                    cancel.cancel();
+                }
+            }
+        });
+    }
+    let timed_out = Arc::new(AtomicBool::new(false));
+
+    // Set by the commands that know how many rows they touched, for
+    // --summary.
+    let mut rows = None;
+    let operation = matches.subcommand_name().unwrap_or_default().to_owned();
+    let start = Instant::now();
+    let dispatch = async {
+        match cli.command {
+            Commands::Insert(args) => {
+                let (mut rng, seed) = seeded_rng(args.seed);
+                info!("Using seed {}", seed);
+                let workload = workload_for(&args, shape, &mut rng)?;
+                if workload.is_empty() {
+                    warn!("No users to insert");
+                    return Ok(());
+                }
+                if !args.json_metrics {
+                    println!("Insert mode: {}", args.insert_mode.as_str());
+                }
+                if args.insert_mode == InsertMode::Truncate {
+                    db.delete_all_users().await?;
+                }
+
+                let workers = cli.workers.unwrap_or(N_WORKERS);
+                let pool_size = cli.pool_size.unwrap_or(workers);
+                let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections).await?;
+                let report =
+                    insert_users(pool, &db_path, workload, workers, &args, &cancel).await?;
+                rows = Some(report.inserted());
+                report.print(args.json_metrics);
+                report.check_retries(args.fail_after_retries)?;
+                if cancel.is_cancelled() {
+                    let progress = format!("inserting {} rows", report.inserted());
+                    return Err(stopped_early(&timed_out, progress));
+                }
+                if report.failed() > 0 {
+                    return Err(PartialFailure {
+                        failed: report.failed(),
+                    }
+                    .into());
+                }
+                if !report.verified() {
+                    return Err(anyhow!(
+                        "expected {} new rows but the table grew by {}",
+                        report.expected_rows,
+                        report.actual_rows
+                    ));
+                }
+            }
+            Commands::Benchmark {
+                worker_set,
+                tx_size_set,
+                csv,
+                insert,
+            } => {
+                let (mut rng, seed) = seeded_rng(insert.seed);
+                info!("Using seed {}", seed);
+                // Every run inserts the same data so the results are comparable.
+                let workload = workload_for(&insert, shape, &mut rng)?;
+                let tx_sizes = if tx_size_set.is_empty() {
+                    vec![insert.tx_size]
+                } else {
+                    tx_size_set.into_iter().map(Some).collect()
+                };
+                let runs = worker_set
+                    .iter()
+                    .flat_map(|&workers| tx_sizes.iter().map(move |&tx_size| (workers, tx_size)));
+
+                let mut results = Vec::with_capacity(worker_set.len() * tx_sizes.len());
+                for (workers, tx_size) in runs {
+                    let insert = InsertArgs {
+                        tx_size,
+                        ..insert.clone()
+                    };
+                    db.delete_all().await?;
+
+                    let workload = workload.clone();
+                    let pool_size = cli.pool_size.unwrap_or(workers);
+                    let pool =
+                        DbPool::open(db.clone(), &db_path, pool_size, cli.max_connections).await?;
+                    let report =
+                        insert_users(pool, &db_path, workload, workers, &insert, &cancel).await?;
+                    if let Err(e) = report.check_retries(insert.fail_after_retries) {
+                        println!("Stopped with {} workers", workers);
+                        print_benchmark(&results, csv);
+                        return Err(e.into());
+                    }
+                    if cancel.is_cancelled() {
+                        let reason = if timed_out.load(Ordering::Relaxed) {
+                            "Timed out"
+                        } else {
+                            "Interrupted"
+                        };
+                        println!(
+                            "{} after inserting {} rows with {} workers",
+                            reason,
+                            report.inserted(),
+                            workers
+                        );
+                        print_benchmark(&results, csv);
+                        if timed_out.load(Ordering::Relaxed) {
+                            return Err(anyhow!("timed out"));
+                        }
+                        return Err(Interrupted {
+                            progress: format!("inserting {} rows", report.inserted()),
+                        }
+                        .into());
+                    }
+                    if report.failed() > 0 {
+                        warn!(
+                            "{} inserts failed with {} workers",
+                            report.failed(),
+                            workers
+                        );
+                    }
+                    if !report.verified() {
+                        warn!(
+                            "Expected {} new rows with {} workers but the table grew by {}",
+                            report.expected_rows, workers, report.actual_rows
+                        );
+                    }
+                    *rows.get_or_insert(0) += report.inserted();
+                    results.push((workers, report));
+                }
+
+                print_benchmark(&results, csv);
+                let failed = results.iter().map(|(_, report)| report.failed()).sum();
+                if failed > 0 {
+                    return Err(PartialFailure { failed }.into());
+                }
+            }
+            Commands::Select {
+                format,
+                output,
+                limit,
+                sample,
+                order_by,
+                desc,
+                show_plan,
+            } => {
+                // Only plain identifiers get this far, but a column the table
+                // lacks would otherwise fail half way through the output.
+                if let Some(column) = &order_by {
+                    if !db.table_columns().await?.contains(column) {
+                        return Err(DbError::UnknownColumn {
+                            table: db.schema.table.clone(),
+                            column: column.clone(),
+                        }
+                        .into());
+                    }
+                }
+                let order = RowOrder::new(sample, order_by, desc);
+                if show_plan {
+                    let sql = db.schema.select_sql(format, &order, limit.is_some());
+                    print_sort_plan(&db.explain(sql, limit_param(limit)).await?, &order);
+                }
+                let out = output_writer(output.as_deref())?;
+                let n_rows = write_users(&db, format, limit, &order, out, &cancel).await?;
+                rows = Some(n_rows);
+                if cancel.is_cancelled() {
+                    return Err(stopped_early(
+                        &timed_out,
+                        format!("writing {} rows", n_rows),
+                    ));
+                }
+            }
+            Commands::ParallelSelect => {
+                let workers = cli.workers.unwrap_or(N_WORKERS);
+                let pool_size = cli.pool_size.unwrap_or(workers);
+                let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections).await?;
+                let n_rows = run_parallel_select(pool, workers, &cancel).await?;
+                rows = Some(n_rows);
+                if cancel.is_cancelled() {
+                    return Err(stopped_early(
+                        &timed_out,
+                        format!("reading {} rows", n_rows),
+                    ));
+                }
+            }
+            Commands::Update {
+                set_prefix,
+                id,
+                name,
+                concurrent,
+            } => match (set_prefix, id, name) {
+                (Some(prefix), _, _) if concurrent => {
+                    let workers = cli.workers.unwrap_or(N_WORKERS);
+                    let pool_size = cli.pool_size.unwrap_or(workers);
+                    let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections).await?;
+                    let n_rows = run_update(pool, prefix, workers, &cancel).await?;
+                    rows = Some(n_rows);
+                    if cancel.is_cancelled() {
+                        let progress = format!("updating {} rows", n_rows);
+                        return Err(stopped_early(&timed_out, progress));
+                    }
+                }
+                (Some(prefix), _, _) => {
+                    let n_rows = db.prefix_names(prefix).await?;
+                    info!("Updated {} rows", n_rows);
+                    rows = Some(n_rows);
+                }
+                (None, Some(id), Some(name)) => {
+                    let n_rows = db.rename_user(id, name).await?;
+                    info!("Updated {} rows", n_rows);
+                    rows = Some(n_rows);
+                }
+                _ => unreachable!("clap requires --set-prefix or --id with --name"),
+            },
+            Commands::Reset => {
+                if db.reset().await? {
+                    info!("Dropped and recreated table {}", db.schema.table);
+                } else {
+                    info!("No table {} to drop, created it", db.schema.table);
+                }
+            }
+            Commands::Delete { filter, params, id } => match id {
+                Some(id) => {
+                    rows = Some(
+                        db.delete_users(Some(ID_FILTER.into()), vec![id.into()])
+                            .await?,
+                    );
+                }
+                None => {
+                    let params = params.into_iter().map(Into::into).collect();
+                    rows = Some(db.delete_users(filter, params).await?);
+                }
+            },
+            Commands::CreateIndex { column } => {
+                db.create_index(column).await?;
+                info!("Index created");
+            }
+            Commands::Explain { query } => {
+                // Each step is indented below its parent, like the sqlite3 shell.
+                let plan = db.explain(query, Vec::new()).await?;
+                let mut depths = std::collections::HashMap::new();
+                for (id, parent, detail) in plan {
+                    let depth = depths.get(&parent).map_or(0, |d| d + 1);
+                    depths.insert(id, depth);
+                    println!("{}{}", "  ".repeat(depth), detail);
+                }
+            }
+            Commands::Vacuum { incremental } => {
+                let before = db_file_size(&db_path)?;
+                db.vacuum(incremental).await?;
+                let after = db_file_size(&db_path)?;
+                match (before, after) {
+                    (Some(before), Some(after)) => {
+                        println!("File size before: {} bytes, after: {} bytes", before, after)
+                    }
+                    _ => println!("Vacuumed; file size is unavailable for in-memory databases"),
+                }
+            }
+            Commands::Repl => run_repl(&db, &cancel).await?,
+            Commands::Backup { dest, force } => {
+                if dest.exists() {
+                    if dest.canonicalize()? == db_path.canonicalize().unwrap_or_default() {
+                        return Err(anyhow!("{} is the database itself", dest.display()));
+                    }
+                    if !force {
+                        return Err(anyhow!(
+                            "{} already exists; pass --force to replace it",
+                            dest.display()
+                        ));
+                    }
+                    std::fs::remove_file(&dest)
+                        .with_context(|| format!("failed to remove {}", dest.display()))?;
+                }
+                db.vacuum_into(&dest).await?;
+                let copy = db_file_size(&dest)?.unwrap_or_default();
+                // In WAL mode recent pages may still be in the -wal file.
+                match (db_file_size(&db_path)?, wal_file_size(&db_path)?) {
+                    (Some(source), Some(wal)) => println!(
+                        "Backed up {} ({} bytes, {} more in the WAL) to {} ({} bytes)",
+                        db_path.display(),
+                        source,
+                        wal,
+                        dest.display(),
+                        copy
+                    ),
+                    _ => println!("Backed up to {} ({} bytes)", dest.display(), copy),
+                }
+            }
+            Commands::Checkpoint => {
+                let before = wal_file_size(&db_path)?;
+                let checkpoint = db.checkpoint().await?;
+                let after = wal_file_size(&db_path)?;
+                if checkpoint.log_frames < 0 {
+                    println!("Not in WAL mode, nothing to checkpoint");
+                } else {
+                    println!(
+                        "Checkpointed {} of {} frames{}",
+                        checkpoint.checkpointed_frames,
+                        checkpoint.log_frames,
+                        if checkpoint.busy {
+                            "; another connection kept it from finishing"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                if let (Some(before), Some(after)) = (before, after) {
+                    println!("WAL size before: {} bytes, after: {} bytes", before, after);
+                }
+            }
+            Commands::Stats { json } => {
+                let mut stats = db.stats().await?;
+                stats.file_size = db_file_size(&db_path)?;
+                stats.print(json);
+            }
+            Commands::Mix(mix) => {
+                if mix.readers == 0 && mix.writers == 0 {
+                    return Err(anyhow!("mix needs at least one reader or writer"));
+                }
+                let pool_size = cli.pool_size.unwrap_or(mix.readers + mix.writers);
+                let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections).await?;
+                let n_rows = run_mix(pool, mix, shape, &cancel).await?;
+                rows = Some(n_rows);
+                if cancel.is_cancelled() {
+                    let progress = format!("writing and reading {} rows", n_rows);
+                    return Err(stopped_early(&timed_out, progress));
+                }
+            }
+            Commands::Fuzz(fuzz) => {
+                let workers = cli.workers.unwrap_or(N_WORKERS);
+                let pool_size = cli.pool_size.unwrap_or(workers);
+                let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections).await?;
+                let ops = run_fuzz(pool, fuzz, workers, shape, &cancel).await?;
+                if cancel.is_cancelled() {
+                    return Err(stopped_early(&timed_out, format!("{} operations", ops)));
+                }
+            }
+            Commands::VerifyUnique => {
+                let duplicates = db.duplicate_names().await?;
+                if duplicates.is_empty() {
+                    println!("no duplicates");
+                    return Ok(());
+                }
+                for (name, count) in &duplicates {
+                    println!("{} appears {} times", name, count);
+                }
+                return Err(anyhow!("{} names are duplicated", duplicates.len()));
+            }
+            Commands::Count { filter, params } => {
+                let count = db.count_users(filter, params).await?;
+                println!("{}", count);
+                rows = Some(count as usize);
+            }
+            Commands::Lookup { name, count } => match (name, count) {
+                (_, Some(count)) => {
+                    let lookups = run_lookups(&db, count, &cancel).await?;
+                    if cancel.is_cancelled() {
+                        return Err(stopped_early(&timed_out, format!("{} lookups", lookups)));
+                    }
+                }
+                (Some(name), None) => match db.find_user(name).await? {
+                    Some(user) => println!("{}", user),
+                    None => println!("not found"),
+                },
+                (None, None) => unreachable!("clap requires a name or --count"),
+            },
+            Commands::InsertPosts {
+                per_user,
+                user_id,
+                tx_size,
+                json_metrics,
+                quiet,
+            } => {
+                db.create_posts_table().await?;
+                let user_ids = match user_id {
+                    Some(id) => vec![id],
+                    None => db.select_ids().await?,
+                };
+                let posts = create_posts(&user_ids, per_user, &mut StdRng::from_entropy());
+                if posts.is_empty() {
+                    warn!("No posts to insert");
+                    return Ok(());
+                }
+
+                let args = InsertArgs {
+                    count: posts.len(),
+                    from_file: None,
+                    duration_secs: None,
+                    tx_size,
+                    on_conflict: OnConflict::Fail,
+                    distribution: Distribution::Static,
+                    rows_per_stmt: MAX_BOUND_PARAMS / 2,
+                    max_inflight: None,
+                    seed: None,
+                    warmup: 0,
+                    fail_after_retries: None,
+                    json_metrics,
+                    latency: false,
+                    backend: Backend::TokioRusqlite,
+                    checkpoint_after: false,
+                    strict: false,
+                    sample_interval_ms: None,
+                    target_rate: None,
+                    begin_mode: BeginMode::Deferred,
+                    insert_mode: InsertMode::Append,
+                    quiet,
+                };
+                let workers = cli.workers.unwrap_or(N_WORKERS);
+                let pool_size = cli.pool_size.unwrap_or(workers);
+                let pool = DbPool::open(db, &db_path, pool_size, cli.max_connections)
+                    .await?
+                    .map(Posts);
+                let report =
+                    run_insertion(pool, Workload::Fixed(posts), workers, args, cancel.clone())
+                        .await?;
+                rows = Some(report.inserted());
+                report.print(json_metrics);
+                if report.failed() > 0 {
+                    return Err(PartialFailure {
+                        failed: report.failed(),
+                    }
+                    .into());
+                }
+            }
+            Commands::Import {
+                path,
+                skip_bad,
+                tx_size,
+                on_conflict,
+            } => {
+                let mut rng = StdRng::from_entropy();
+                let (users, skipped) = load_csv(&path, shape, skip_bad, &mut rng)?;
+                let options = InsertOptions {
+                    on_conflict,
+                    rows_per_stmt: MAX_BOUND_PARAMS,
+                    begin_mode: BeginMode::Deferred,
+                };
+                let (mut inserted, mut ignored) = (0, 0);
+                for chunk in users.chunks(tx_size) {
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+                    let outcome = db
+                        .insert_many(chunk.to_vec(), options, cancel.clone())
+                        .await?;
+                    inserted += outcome.inserted;
+                    ignored += outcome.attempted - outcome.inserted;
+                    rows = Some(inserted);
+                }
+                println!(
+                    "Imported {} rows from {}, ignored {}, skipped {} bad lines",
+                    inserted,
+                    path.display(),
+                    ignored,
+                    skipped
+                );
+            }
+        };
+
+        Ok(())
+    };
+
+    let result = match cli.timeout_secs {
+        Some(secs) => {
+            run_with_timeout(dispatch, secs, cancel.clone(), Arc::clone(&timed_out)).await
+        }
+        None => dispatch.await,
+    };
+    if let Some(profile) = profile.filter(|_| cli.profile) {
+        profile.print();
+    }
+    if let Some(format) = cli.summary {
+        CommandSummary {
+            operation: &operation,
+            rows,
+            elapsed: start.elapsed(),
+            error: result.as_ref().err(),
+        }
+        .print(format);
+    }
+
+    result
+}
+
+/// Answers every request on `listener` with the profile's counts as JSON,
+/// until the process exits.
+async fn serve_status(listener: TcpListener, profile: Arc<Profile>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Status connection failed: {}", e);
+                continue;
+            }
+        };
+        let body = profile.to_json().to_string();
+        tokio::spawn(async move {
+            // Every path gets the same answer, so the request is only read
+            // to keep the client from seeing a reset.
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                debug!("Failed to send status: {}", e);
+            }
+        });
+    }
+}
+
+/// Runs `dispatch`, cancelling it after `secs` seconds and dropping it
+/// outright if it hasn't finished a grace period later.
+async fn run_with_timeout(
+    dispatch: impl Future<Output = anyhow::Result<()>>,
+    secs: u64,
+    cancel: CancellationToken,
+    timed_out: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let limit = Duration::from_secs(secs);
+    // Inserts stop through the cancellation token so their transactions
+    // commit; anything still running after the grace period is dropped.
+    tokio::spawn(async move {
+        tokio::time::sleep(limit).await;
+        warn!(
+            "Timed out after {:?}, committing in-flight transactions",
+            limit
+        );
+        timed_out.store(true, Ordering::Relaxed);
+        cancel.cancel();
+    });
+    match tokio::time::timeout(limit + TIMEOUT_GRACE, dispatch).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timed out after {:?}", limit)),
+    }
+}
+
+fn print_benchmark(results: &[(usize, InsertionReport)], csv: bool) {
+    // Only shown with --checkpoint-after, which applies to every run alike.
+    let checkpointed = results.iter().any(|(_, r)| r.checkpoint.is_some());
+    let checkpoint = |report: &InsertionReport| report.checkpoint.as_ref().map(|(d, _)| *d);
+    if csv {
+        let header = if checkpointed { ",checkpoint_secs" } else { "" };
+        println!("workers,tx_size,elapsed_secs,rows_per_sec{}", header);
+        for (workers, report) in results {
+            let column = match checkpoint(report) {
+                Some(elapsed) if checkpointed => format!(",{}", elapsed.as_secs_f64()),
+                _ if checkpointed => ",".to_owned(),
+                _ => String::new(),
+            };
+            println!(
+                "{},{},{},{}{}",
+                workers,
+                report.tx_size,
+                report.elapsed.as_secs_f64(),
+                report.rows_per_sec(),
+                column
+            );
+        }
+        return;
+    }
+
+    let header = if checkpointed {
+        format!("  {:>12}", "checkpoint")
+    } else {
+        String::new()
+    };
+    println!(
+        "{:>8}  {:>8}  {:>12}  {:>12}{}",
+        "workers", "tx size", "elapsed", "rows/s", header
+    );
+    for (workers, report) in results {
+        let column = match checkpoint(report) {
+            Some(elapsed) if checkpointed => format!("  {:>12}", format!("{:.3?}", elapsed)),
+            _ if checkpointed => format!("  {:>12}", "-"),
+            _ => String::new(),
+        };
+        println!(
+            "{:>8}  {:>8}  {:>12}  {:>12.0}{}",
+            workers,
+            report.tx_size,
+            format!("{:.3?}", report.elapsed),
+            report.rows_per_sec(),
+            column
+        );
+    }
+}
+
+/// Prints `plan` to stderr, so it stays out of the selected rows, followed by
+/// whether SQLite has to sort the rows itself to return them in `order`.
+fn print_sort_plan(plan: &[(i64, i64, String)], order: &RowOrder) {
+    for (_, _, detail) in plan {
+        eprintln!("Query plan: {}", detail);
+    }
+    let sorted = plan
+        .iter()
+        .any(|(_, _, detail)| detail.starts_with("USE TEMP B-TREE FOR ORDER BY"));
+    match order {
+        RowOrder::Scan => {}
+        _ if sorted => eprintln!("Sort: rows are sorted in a temporary B-tree"),
+        _ => eprintln!("Sort: none, the scan returns rows in order"),
+    }
+}
+
+/// Binds `limit`, if there is one, as the `?1` of a limited select.
+fn limit_param(limit: Option<usize>) -> Vec<rusqlite::types::Value> {
+    limit
+        .map(|limit| rusqlite::types::Value::Integer(i64::try_from(limit).unwrap_or(i64::MAX)))
+        .into_iter()
+        .collect()
+}
+
+/// A buffered writer to `--output`'s file, or to stdout without one.
+fn output_writer(output: Option<&Path>) -> std::io::Result<BufWriter<Box<dyn Write>>> {
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    Ok(BufWriter::new(out))
+}
+
+/// Writes every user, or at most `limit` of them, in `order` and `format`.
+/// The streamed formats stop after the rows so far once `cancel` fires.
+async fn write_users(
+    db: &DB,
+    format: OutputFormat,
+    limit: Option<usize>,
+    order: &RowOrder,
+    mut out: impl Write,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let stream = || db.select_users(order, limit);
+    let mut n_rows = 0;
+    match format {
+        OutputFormat::Debug => {
+            let mut users = stream();
+            while let Some(user) = recv_until(&mut users, cancel).await {
+                writeln!(out, "{:#?}", user?)?;
+                n_rows += 1;
+            }
+        }
+        OutputFormat::Json => {
+            let sql = db.schema.select_all_columns_sql(order, limit.is_some());
+            let mut rows = db.select_rows_json(sql, limit_param(limit)).await?;
+            if db.schema.payload {
+                for row in &mut rows {
+                    decode_json_payload(row)?;
+                }
+            }
+            serde_json::to_writer(&mut out, &rows)?;
+            writeln!(out)?;
+            n_rows = rows.len();
+        }
+        OutputFormat::Jsonl => {
+            let mut users = stream();
+            while let Some(user) = recv_until(&mut users, cancel).await {
+                serde_json::to_writer(&mut out, &user?)?;
+                writeln!(out)?;
+                n_rows += 1;
+                // Flushed per row so a consumer on the other end of a pipe
+                // sees rows as they come rather than when the buffer fills.
+                out.flush()?;
+            }
+        }
+        OutputFormat::Csv => {
+            // Written by hand so an empty table still gets a header row.
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut out);
+            let mut header = vec!["id", "name", "email", "created_at"];
+            if db.schema.payload {
+                header.push("payload");
+            }
+            writer.write_record(header)?;
+            let mut users = stream();
+            while let Some(user) = recv_until(&mut users, cancel).await {
+                writer.serialize(user?)?;
+                n_rows += 1;
+            }
+            writer.flush()?;
+        }
+    }
+
+    out.flush()?;
+    Ok(n_rows)
+}
+
+/// The next item from `receiver`, or `None` once `cancel` fires.
+async fn recv_until<T>(receiver: &mut mpsc::Receiver<T>, cancel: &CancellationToken) -> Option<T> {
+    tokio::select! {
+        item = receiver.recv() => item,
+        _ = cancel.cancelled() => None,
+    }
+}
+
+/// Warms up and runs the measured insertion of users through the backend
+/// `args` asks for.
+async fn insert_users(
+    pool: DbPool,
+    path: &Path,
+    workload: Workload<User>,
+    n_workers: usize,
+    args: &InsertArgs,
+    cancel: &CancellationToken,
+) -> anyhow::Result<InsertionReport> {
+    let primary = pool.get(0);
+    let mut report = match args.backend {
+        Backend::TokioRusqlite => {
+            measured_insertion(pool, path, workload, n_workers, args, cancel).await?
+        }
+        Backend::Blocking => {
+            if path == Path::new(MEMORY_DB) {
+                return Err(anyhow!(
+                    "--backend blocking needs a database file, every extra :memory: \
+                     connection would be a separate database"
+                ));
+            }
+            let pool = pool.into_blocking(path).await?;
+            measured_insertion(pool, path, workload, n_workers, args, cancel).await?
+        }
+    };
+    if args.checkpoint_after && !cancel.is_cancelled() {
+        let start = Instant::now();
+        let checkpoint = primary.full_checkpoint().await?;
+        report.checkpoint = Some((start.elapsed(), checkpoint));
+    }
+
+    Ok(report)
+}
+
+/// The warm-up runs followed by the measured one, with the file size
+/// sampled during the latter if `--sample-interval-ms` asks for it.
+async fn measured_insertion<R: Repository>(
+    pool: DbPool<R>,
+    path: &Path,
+    workload: Workload<R::Entity>,
+    n_workers: usize,
+    args: &InsertArgs,
+    cancel: &CancellationToken,
+) -> anyhow::Result<InsertionReport> {
+    warm_up(&pool, &workload, n_workers, args, cancel).await?;
+    let sampler = match args.sample_interval_ms {
+        Some(_) if path == Path::new(MEMORY_DB) => {
+            warn!("A :memory: database has no file size to sample");
+            None
+        }
+        Some(ms) => Some(SizeSampler::start(path, Duration::from_millis(ms))),
+        None => None,
+    };
+    let report = run_insertion(pool, workload, n_workers, args.clone(), cancel.clone()).await;
+    let growth = match sampler {
+        Some(sampler) => Some(sampler.finish().await?),
+        None => None,
+    };
+
+    Ok(InsertionReport { growth, ..report? })
+}
+
+/// The database file's size at one point of a run.
+#[derive(Clone, Copy, Debug, Serialize)]
+struct SizeSample {
+    elapsed_ms: u64,
+    bytes: u64,
+}
+
+/// A background task recording the database file's size at a fixed interval
+/// until it is finished.
+struct SizeSampler {
+    stop: CancellationToken,
+    handle: tokio::task::JoinHandle<Vec<SizeSample>>,
+}
+
+impl SizeSampler {
+    fn start(path: &Path, interval: Duration) -> Self {
+        let stop = CancellationToken::new();
+        let path = path.to_owned();
+        let handle = tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                let start = Instant::now();
+                let mut samples = Vec::new();
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    tokio::select! {
+                        _ = ticks.tick() => {}
+                        _ = stop.cancelled() => break,
+                    }
+                    match std::fs::metadata(&path) {
+                        Ok(metadata) => samples.push(SizeSample {
+                            elapsed_ms: start.elapsed().as_millis() as u64,
+                            bytes: metadata.len(),
+                        }),
+                        Err(e) => debug!("Failed to read the size of {}: {}", path.display(), e),
+                    }
+                }
+                // One more once the workers are done, so the last sample shows
+                // where the run ended up.
+                if let Ok(metadata) = std::fs::metadata(&path) {
+                    samples.push(SizeSample {
+                        elapsed_ms: start.elapsed().as_millis() as u64,
+                        bytes: metadata.len(),
+                    });
+                }
+                samples
+            }
+        });
+
+        Self { stop, handle }
+    }
+
+    async fn finish(self) -> anyhow::Result<Vec<SizeSample>> {
+        self.stop.cancel();
+        Ok(self.handle.await?)
+    }
+}
+
+/// Runs `args.warmup` insertions whose timings are thrown away, so the measured
+/// run doesn't pay for a cold page cache and fresh connections. The table is
+/// cleared after every run.
+async fn warm_up<R: Repository>(
+    pool: &DbPool<R>,
+    workload: &Workload<R::Entity>,
+    n_workers: usize,
+    args: &InsertArgs,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    for run in 1..=args.warmup {
+        let report = run_insertion(
+            pool.clone(),
+            workload.clone(),
+            n_workers,
+            args.clone(),
+            cancel.clone(),
+        )
+        .await?;
+        pool.get(0).delete_all().await?;
+        info!(
+            "Warmup {}/{} (discarded): {} rows in {:.3?} ({:.0} rows/s)",
+            run,
+            args.warmup,
+            report.inserted(),
+            report.elapsed,
+            report.rows_per_sec()
+        );
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+    }
+    if args.warmup > 0 {
+        info!("Warmup done, starting the measured run");
+    }
+
+    Ok(())
+}
+
+pub async fn run_insertion<R: Repository>(
+    pool: DbPool<R>,
+    workload: Workload<R::Entity>,
+    n_workers: usize,
+    args: InsertArgs,
+    cancel: CancellationToken,
+) -> anyhow::Result<InsertionReport> {
+    // Workers beyond the number of entities would only get empty slices.
+    let n_workers = match workload.len() {
+        Some(len) if len < n_workers => {
+            info!(
+                "Starting {} workers instead of {}, one per entity",
+                len.max(1),
+                n_workers
+            );
+            len.max(1)
+        }
+        _ => n_workers,
+    };
+    let mut handles = Vec::with_capacity(n_workers);
+    // A timed run can only be checked against what the workers say they did.
+    let expected_rows = match &workload {
+        Workload::Fixed(entities) if pool.get(0).enforces_unique().await? => Some(
+            entities
+                .iter()
+                .map(R::unique_key)
+                .collect::<std::collections::HashSet<_>>()
+                .len(),
+        ),
+        Workload::Fixed(entities) => Some(entities.len()),
+        Workload::Timed { .. } => None,
+    };
+    let rows_before = pool.get(0).count().await?;
+    let chunk_size = args
+        .tx_size
+        .unwrap_or(match workload.len() {
+            // One transaction per worker leaves nothing to pace.
+            Some(len) if args.target_rate.is_none() => len.div_ceil(n_workers),
+            _ => args.rows_per_stmt,
+        })
+        .max(1);
+    // A child, so --fail-after-retries stops this run's workers without
+    // looking like a Ctrl-C to the caller.
+    let mut shared = SharedRun::new(args.max_inflight, cancel.child_token());
+    shared.worker_rate = args.target_rate.map(|rate| rate as f64 / n_workers as f64);
+    let bar = progress_bar(workload.len().map(|len| len as u64), args.quiet);
+    let ticker = bar.clone().map(|bar| {
+        let progress = Arc::clone(&shared.progress);
+        tokio::spawn(async move {
+            loop {
+                bar.set_position(progress.load(Ordering::Relaxed));
+                tokio::time::sleep(PROGRESS_INTERVAL).await;
+            }
+        })
+    });
+    let start = Instant::now();
+    let sources = workload.into_sources(n_workers, args.distribution, start);
+
+    let mut committed = Vec::with_capacity(n_workers);
+    for (worker, source) in (1..=n_workers).zip(sources) {
+        let span = tracing::info_span!("worker", id = worker);
+        let shared = shared.for_worker();
+        committed.push(Arc::clone(&shared.committed));
+        handles.push(tokio::task::spawn(
+            batch_insertion(
+                pool.get(worker - 1),
+                format!("Worker: {}", worker),
+                source,
+                chunk_size,
+                args.clone(),
+                shared,
+            )
+            .instrument(span),
+        ))
+    }
+
+    let mut workers = Vec::with_capacity(n_workers);
+    let mut strict_failure = None;
+    let mut panicked = None;
+    // Workers stop at the next row once cancelled, so this never waits long.
+    for ((worker, handle), committed) in (1..).zip(handles).zip(committed) {
+        match handle.await {
+            Ok(Ok(stats)) => workers.push(stats),
+            Ok(Err(e)) => {
+                strict_failure.get_or_insert((worker, e));
+            }
+            Err(e) if e.is_panic() => {
+                // Only the chunks it saw commit are counted.
+                let rows = committed.load(Ordering::Relaxed);
+                error!(
+                    "Worker: {} panicked after committing {} rows: {}",
+                    worker, rows, e
+                );
+                shared.cancel.cancel();
+                panicked.get_or_insert((worker, rows));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let elapsed = start.elapsed();
+    if let (Some(bar), Some(ticker)) = (bar, ticker) {
+        ticker.abort();
+        bar.finish_and_clear();
+        PROGRESS.remove(&bar);
+    }
+    if let Some((worker, rows)) = panicked {
+        return Err(anyhow!(
+            "worker {} panicked after committing {} rows; see the log for the panic",
+            worker,
+            rows
+        ));
+    }
+    if let Some((worker, e)) = strict_failure {
+        return Err(anyhow::Error::new(e).context(format!(
+            "worker {} failed, stopping the run under --strict",
+            worker
+        )));
+    }
+    let rows_after = pool.get(0).count().await?;
+
+    let expected_rows = expected_rows.unwrap_or_else(|| workers.iter().map(|w| w.inserted).sum());
+
+    Ok(InsertionReport {
+        workers,
+        elapsed,
+        expected_rows,
+        actual_rows: (rows_after - rows_before).max(0) as usize,
+        on_conflict: args.on_conflict,
+        backend: args.backend,
+        begin_mode: args.begin_mode,
+        tx_size: chunk_size,
+        checkpoint: None,
+        growth: None,
+        target_rate: args.target_rate,
+    })
+}
+
+/// Prepends `prefix` to every name, splitting the rows across `n_workers` the
+/// same way `run_insertion` splits users. Once `cancel` fires every worker
+/// commits the renames it has made and stops.
+async fn run_update(
+    pool: DbPool,
+    prefix: String,
+    n_workers: usize,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let ids = pool.get(0).select_ids().await?;
+    let start = Instant::now();
+
+    let mut handles = Vec::with_capacity(n_workers);
+    for worker in 0..n_workers {
+        let db = pool.get(worker);
+        let prefix = prefix.clone();
+        let cancel = cancel.clone();
+        let ids = ids
+            .iter()
+            .skip(worker)
+            .step_by(n_workers)
+            .copied()
+            .collect();
+        handles.push(tokio::task::spawn(async move {
+            let start = Instant::now();
+            let n_rows = db.prefix_names_by_id(prefix, ids, cancel).await;
+            (n_rows, start.elapsed())
+        }));
+    }
+
+    let mut total = 0;
+    for (worker, handle) in handles.into_iter().enumerate() {
+        let (n_rows, elapsed) = handle.await?;
+        let n_rows = n_rows?;
+        println!(
+            "Worker: {} updated {} rows in {:.3?}",
+            worker + 1,
+            n_rows,
+            elapsed
+        );
+        total += n_rows;
+    }
+    println!(
+        "Updated {} rows with {} workers in {:.3?}",
+        total,
+        n_workers,
+        start.elapsed()
+    );
+
+    Ok(total)
+}
+
+/// Splits the ids from `lo` to `hi` into at most `n` contiguous ranges of
+/// nearly equal width, fewer when there are fewer ids than `n`.
+fn split_id_range(lo: i64, hi: i64, n: usize) -> Vec<(i64, i64)> {
+    let span = i128::from(hi) - i128::from(lo) + 1;
+    let n = (n as i128).min(span);
+    (0..n)
+        .map(|i| {
+            let start = i128::from(lo) + span * i / n;
+            let end = i128::from(lo) + span * (i + 1) / n - 1;
+            (start as i64, end as i64)
+        })
+        .collect()
+}
+
+/// Reads the table with `n_workers` workers at once, each selecting its own
+/// slice of the id range, and reports every worker's rows and the total rate.
+/// Slices cover equal id ranges, so gaps left by deletes show up as uneven
+/// row counts. Once `cancel` fires the workers stop reading.
+async fn run_parallel_select(
+    pool: DbPool,
+    n_workers: usize,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let Some((lo, hi)) = pool.get(0).id_range().await? else {
+        warn!("No users to read");
+        return Ok(0);
+    };
+    let ranges = split_id_range(lo, hi, n_workers);
+    let n_workers = ranges.len();
+    let start = Instant::now();
+
+    let mut handles = Vec::with_capacity(n_workers);
+    for (worker, &(lo, hi)) in ranges.iter().enumerate() {
+        let db = pool.get(worker);
+        let cancel = cancel.clone();
+        handles.push(tokio::task::spawn(async move {
+            let start = Instant::now();
+            let n_rows = db.read_id_range(lo, hi, cancel).await;
+            (n_rows, start.elapsed())
+        }));
+    }
+
+    let mut total = 0;
+    for (worker, (handle, (lo, hi))) in handles.into_iter().zip(ranges).enumerate() {
+        let (n_rows, elapsed) = handle.await?;
+        let n_rows = n_rows?;
+        println!(
+            "Worker: {} read {} rows with ids {}..={} in {:.3?}",
+            worker + 1,
+            n_rows,
+            lo,
+            hi,
+            elapsed
+        );
+        total += n_rows;
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "Read {} rows with {} workers in {:.3?} ({:.0} rows/s)",
+        total,
+        n_workers,
+        elapsed,
+        total as f64 / elapsed.as_secs_f64()
+    );
+
+    Ok(total)
+}
+
+/// Looks up `count` names picked at random from the table, one query each,
+/// and reports the lookup rate. Stops early once `cancel` fires; returns how
+/// many lookups ran.
+async fn run_lookups(db: &DB, count: usize, cancel: &CancellationToken) -> anyhow::Result<usize> {
+    let names = db.select_names().await?;
+    if names.is_empty() {
+        warn!("No users to look up");
+        return Ok(0);
+    }
+
+    let mut rng = thread_rng();
+    let start = Instant::now();
+    let (mut lookups, mut found) = (0, 0);
+    while lookups < count && !cancel.is_cancelled() {
+        let name = names[rng.gen_range(0..names.len())].clone();
+        if db.find_user(name).await?.is_some() {
+            found += 1;
+        }
+        lookups += 1;
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} lookups, {} found, in {:.3?} ({:.0} lookups/s)",
+        lookups,
+        found,
+        elapsed,
+        lookups as f64 / elapsed.as_secs_f64()
+    );
+
+    Ok(lookups)
+}
+
+/// Reads statements from stdin until EOF, `.quit` or Ctrl-C. A failing
+/// statement is reported and the loop carries on.
+async fn run_repl(db: &DB, cancel: &CancellationToken) -> anyhow::Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut statement = String::new();
+    loop {
+        if interactive {
+            print!(
+                "{}",
+                if statement.is_empty() {
+                    "sql> "
+                } else {
+                    " ..> "
+                }
+            );
+            std::io::stdout().flush()?;
+        }
+        let line = tokio::select! {
+            line = lines.next_line() => line?,
+            _ = cancel.cancelled() => None,
+        };
+        let Some(line) = line else {
+            break;
+        };
+
+        if statement.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ".quit" => break,
+                meta if meta.starts_with('.') => {
+                    eprintln!("Unknown command {}; only .quit is supported", meta);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        statement.push_str(&line);
+        statement.push('\n');
+        for sql in split_statements(&mut statement) {
+            match db.run_statement(sql).await {
+                Ok(StatementResult::Rows(rows)) => {
+                    for row in &rows {
+                        println!("{}", row);
+                    }
+                    println!("({} rows)", rows.len());
+                }
+                Ok(StatementResult::Changed(n_rows)) => println!("{} rows affected", n_rows),
+                Ok(StatementResult::Done) => println!("OK"),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+    }
+
+    if !statement.trim().is_empty() {
+        warn!("Discarding an unterminated statement; end it with `;`");
+    }
+
+    Ok(())
+}
+
+/// Takes every complete statement off the front of `buffer`, leaving a
+/// partial one behind. A `;` only ends a statement where SQLite's own shell
+/// would end it, so not inside quotes, comments or a trigger body.
+fn split_statements(buffer: &mut String) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for (end, _) in buffer.match_indices(';') {
+        let sql = &buffer[start..=end];
+        if is_complete_sql(sql) {
+            statements.push(sql.trim().to_owned());
+            start = end + 1;
+        }
+    }
+    buffer.drain(..start);
+    if buffer.trim().is_empty() {
+        buffer.clear();
+    }
+
+    statements
+}
+
+fn is_complete_sql(sql: &str) -> bool {
+    let Ok(sql) = std::ffi::CString::new(sql) else {
+        return false;
+    };
+    // SAFETY: `sql` is a NUL-terminated string that outlives the call, and
+    // sqlite3_complete only reads it.
+    unsafe { rusqlite::ffi::sqlite3_complete(sql.as_ptr()) != 0 }
+}
+
+/// Readers get the pool's connections after the writers', so with a large
+/// enough pool every task has its own connection and reads really run in
+/// parallel with the writes. Once `cancel` fires the writers commit their
+/// transactions and every task stops early; returns the rows written and
+/// read.
+async fn run_mix(
+    pool: DbPool,
+    args: MixArgs,
+    shape: UserShape,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let MixArgs {
+        duration_secs,
+        readers,
+        writers,
+        tx_size,
+        count_only,
+        delete_after_secs,
+    } = args;
+    let duration = Duration::from_secs(duration_secs);
+    let start = Instant::now();
+    let deadline = start + duration;
+    let rows_before = match delete_after_secs {
+        Some(_) => pool.get(0).count_users(None, Vec::new()).await?,
+        None => 0,
+    };
+    // Gets a connection of its own when the pool is larger than the readers
+    // and writers need.
+    let deleter = delete_after_secs.map(|secs| {
+        let db = pool.get(writers + readers);
+        let cancel = cancel.clone();
+        tokio::task::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(secs)) => {}
+                _ = cancel.cancelled() => return Ok(0),
+            }
+            db.delete_users(None, Vec::new()).await
+        })
+    });
+
+    let mut writer_handles = Vec::with_capacity(writers);
+    for worker in 0..writers {
+        let db = pool.get(worker);
+        let cancel = cancel.clone();
+        let span = tracing::info_span!("writer", id = worker + 1);
+        writer_handles.push(tokio::task::spawn(
+            async move {
+                let mut rng = StdRng::from_entropy();
+                let mut stats = RoleStats::default();
+                while Instant::now() < deadline && !cancel.is_cancelled() {
+                    let users = create_users(tx_size, shape, &mut rng);
+                    let options = InsertOptions {
+                        on_conflict: OnConflict::Fail,
+                        rows_per_stmt: tx_size,
+                        begin_mode: BeginMode::Deferred,
+                    };
+                    match db.insert_many(users, options, cancel.clone()).await {
+                        Ok(outcome) => stats.rows += outcome.inserted,
+                        Err(e) => {
+                            debug!("{}", e);
+                            stats.record_error(&e);
+                        }
+                    }
+                    stats.ops += 1;
+                }
+                stats
+            }
+            .instrument(span),
+        ));
+    }
+
+    let mut reader_handles = Vec::with_capacity(readers);
+    for reader in 0..readers {
+        let db = pool.get(writers + reader);
+        let cancel = cancel.clone();
+        let span = tracing::info_span!("reader", id = reader + 1);
+        reader_handles.push(tokio::task::spawn(
+            async move {
+                let mut stats = RoleStats::default();
+                while Instant::now() < deadline && !cancel.is_cancelled() {
+                    let read = if count_only {
+                        db.count_users(None, Vec::new()).await.map(|n| n as usize)
+                    } else {
+                        db.select_all_users().await.map(|users| users.len())
+                    };
+                    match read {
+                        Ok(rows) => stats.rows += rows,
+                        Err(e) => {
+                            debug!("{}", e);
+                            stats.record_error(&e);
+                        }
+                    }
+                    stats.ops += 1;
+                }
+                stats
+            }
+            .instrument(span),
+        ));
+    }
+
+    let mut written = RoleStats::default();
+    for handle in writer_handles {
+        written.merge(handle.await?);
+    }
+    let mut read = RoleStats::default();
+    for handle in reader_handles {
+        read.merge(handle.await?);
+    }
+
+    let secs = start.elapsed().min(duration).as_secs_f64();
+    println!(
+        "Writers: {} transactions, {} rows ({:.0} rows/s), {} busy, {} errors",
+        written.ops,
+        written.rows,
+        written.rows as f64 / secs,
+        written.busy,
+        written.errors
+    );
+    println!(
+        "Readers: {} selects ({:.0} selects/s), {} rows read, {} busy, {} errors",
+        read.ops,
+        read.ops as f64 / secs,
+        read.rows,
+        read.busy,
+        read.errors
+    );
+    if writers > 0 && read.ops > 0 && read.busy == 0 {
+        println!("No read was blocked by the writers");
+    }
+
+    if let Some(deleter) = deleter {
+        let deleted = deleter.await??;
+        let db = pool.get(0);
+        let problems = db.integrity_check().await?;
+        if !problems.is_empty() {
+            return Err(anyhow!(
+                "integrity check failed after the delete: {}",
+                problems.join("; ")
+            ));
+        }
+        // Every insert and the delete are whole transactions, so the count
+        // has to add up exactly whichever order they committed in.
+        let expected = rows_before + written.rows as i64 - deleted as i64;
+        let actual = db.count_users(None, Vec::new()).await?;
+        println!(
+            "Deleted {} rows mid-run; table holds {} rows ({} before + {} inserted - {} deleted)",
+            deleted, actual, rows_before, written.rows, deleted
+        );
+        if actual != expected {
+            return Err(anyhow!(
+                "expected {} rows after the delete but the table holds {}",
+                expected,
+                actual
+            ));
+        }
+    }
+
+    Ok(written.rows + read.rows)
+}
+
+/// What one kind of fuzz operation ran into.
+#[derive(Clone, Copy, Debug, Default)]
+struct FuzzCounts {
+    ops: usize,
+    busy: usize,
+    /// UNIQUE violations, which random names and renames can cause.
+    conflicts: usize,
+    /// Everything else, which points at a bug.
+    errors: usize,
+}
+
+/// Runs `n_workers` tasks that each pick weighted random operations until
+/// `args.duration_secs` are up or `cancel` fires. Worker `i` seeds its
+/// generator with `seed + i`, so a seed replays the same choices, though the
+/// interleaving between workers can still differ. Returns how many operations
+/// ran.
+async fn run_fuzz(
+    pool: DbPool,
+    args: FuzzArgs,
+    n_workers: usize,
+    shape: UserShape,
+    cancel: &CancellationToken,
+) -> anyhow::Result<usize> {
+    let choose =
+        WeightedIndex::new(args.weights()).map_err(|e| anyhow!("invalid fuzz weights: {}", e))?;
+    let (_, seed) = seeded_rng(args.seed);
+    info!("Using seed {}", seed);
+    let deadline = Instant::now() + Duration::from_secs(args.duration_secs);
+    // Ids to update and delete are picked up to the highest one seen.
+    let max_id = Arc::new(AtomicI64::new(
+        pool.get(0).select_ids().await?.last().copied().unwrap_or(0),
+    ));
+
+    let mut handles = Vec::with_capacity(n_workers);
+    for worker in 0..n_workers {
+        let db = pool.get(worker);
+        let choose = choose.clone();
+        let max_id = Arc::clone(&max_id);
+        let cancel = cancel.clone();
+        let span = tracing::info_span!("fuzzer", id = worker + 1);
+        handles.push(tokio::task::spawn(
+            async move {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(worker as u64));
+                let mut counts = [FuzzCounts::default(); StatementKind::ALL.len()];
+                while Instant::now() < deadline && !cancel.is_cancelled() {
+                    let kind = StatementKind::ALL[rng.sample(&choose)];
+                    let id = rng.gen_range(1..=max_id.load(Ordering::Relaxed).max(1));
+                    let result = match kind {
+                        StatementKind::Insert => {
+                            let options = InsertOptions {
+                                on_conflict: OnConflict::Fail,
+                                rows_per_stmt: 1,
+                                begin_mode: BeginMode::Deferred,
+                            };
+                            let users = create_users(1, shape, &mut rng);
+                            db.insert_many(users, options, cancel.clone())
+                                .await
+                                .map(|outcome| {
+                                    if let Some(rowid) = outcome.last_rowid {
+                                        max_id.fetch_max(rowid, Ordering::Relaxed);
+                                    }
+                                })
+                        }
+                        StatementKind::Select => {
+                            let name = generate_name(shape.name_len, &mut rng);
+                            db.find_user(name).await.map(drop)
+                        }
+                        StatementKind::Update => {
+                            let name = generate_name(shape.name_len, &mut rng);
+                            db.rename_user(id, name).await.map(drop)
+                        }
+                        StatementKind::Delete => db
+                            .delete_users(
+                                Some(ID_FILTER.to_owned()),
+                                vec![rusqlite::types::Value::Integer(id)],
+                            )
+                            .await
+                            .map(drop),
+                    };
+
+                    let counts = &mut counts[kind as usize];
+                    counts.ops += 1;
+                    match result {
+                        Ok(()) => {}
+                        Err(e) if e.is_busy() => counts.busy += 1,
+                        Err(DbError::UniqueViolation(_)) => counts.conflicts += 1,
+                        // Renames aren't classified like inserts are.
+                        Err(DbError::Query(e)) if is_unique_violation(&e) => counts.conflicts += 1,
+                        Err(e) => {
+                            if counts.errors < MAX_LOGGED_ERRORS {
+                                error!("{} failed (seed {}): {}", kind.as_str(), seed, e);
+                            }
+                            counts.errors += 1;
+                        }
+                    }
+                }
+                counts
+            }
+            .instrument(span),
+        ));
+    }
+
+    let mut totals = [FuzzCounts::default(); StatementKind::ALL.len()];
+    let mut panics = 0;
+    for (worker, handle) in handles.into_iter().enumerate() {
+        match handle.await {
+            Ok(counts) => {
+                for (total, counts) in totals.iter_mut().zip(counts) {
+                    total.ops += counts.ops;
+                    total.busy += counts.busy;
+                    total.conflicts += counts.conflicts;
+                    total.errors += counts.errors;
+                }
+            }
+            Err(e) if e.is_panic() => {
+                error!("Fuzzer {} panicked (seed {}): {}", worker + 1, seed, e);
+                panics += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    println!(
+        "{:<9}  {:>10}  {:>8}  {:>9}  {:>8}",
+        "statement", "ops", "busy", "conflicts", "errors"
+    );
+    for kind in StatementKind::ALL {
+        let counts = totals[kind as usize];
+        println!(
+            "{:<9}  {:>10}  {:>8}  {:>9}  {:>8}",
+            kind.as_str(),
+            counts.ops,
+            counts.busy,
+            counts.conflicts,
+            counts.errors
+        );
+    }
+
+    let problems = pool.get(0).integrity_check().await?;
+    for problem in &problems {
+        error!("Integrity check (seed {}): {}", seed, problem);
+    }
+    let errors: usize = totals.iter().map(|counts| counts.errors).sum();
+    if errors > 0 || panics > 0 || !problems.is_empty() {
+        return Err(anyhow!(
+            "seed {} hit {} unexpected errors, {} panics and {} integrity problems; \
+             rerun with --seed {} to replay",
+            seed,
+            errors,
+            panics,
+            problems.len(),
+            seed
+        ));
+    }
+    println!(
+        "Seed {}: no unexpected errors, integrity check passed",
+        seed
+    );
+
+    Ok(totals.iter().map(|counts| counts.ops).sum())
+}
+
+/// State shared by every worker of one `run_insertion` call.
+#[derive(Clone)]
+pub struct SharedRun {
+    /// Caps how many inserts are in flight across all workers.
+    inflight: Arc<Semaphore>,
+    /// Rows committed so far, read by the progress bar.
+    progress: Arc<AtomicU64>,
+    /// Inserts across all workers that ran out of busy retries.
+    exhausted: Arc<AtomicUsize>,
+    /// Rows per second each worker is paced to, if any.
+    worker_rate: Option<f64>,
+    /// Rows committed by the worker holding this copy, so `run_insertion`
+    /// can still tell how far a worker got if it panics.
+    committed: Arc<AtomicU64>,
+    cancel: CancellationToken,
+}
+
+impl SharedRun {
+    /// State for a run allowing `max_inflight` concurrent inserts (unlimited
+    /// when `None`) that stops once `cancel` fires.
+    pub fn new(max_inflight: Option<usize>, cancel: CancellationToken) -> Self {
+        Self {
+            inflight: Arc::new(Semaphore::new(
+                max_inflight
+                    .unwrap_or(Semaphore::MAX_PERMITS)
+                    .min(Semaphore::MAX_PERMITS),
+            )),
+            progress: Arc::new(AtomicU64::new(0)),
+            exhausted: Arc::new(AtomicUsize::new(0)),
+            worker_rate: None,
+            committed: Arc::new(AtomicU64::new(0)),
+            cancel,
+        }
+    }
+
+    /// A copy for one worker, sharing everything but `committed`.
+    fn for_worker(&self) -> Self {
+        Self {
+            committed: Arc::new(AtomicU64::new(0)),
+            ..self.clone()
+        }
+    }
+}
+
+/// Inserts everything `source` hands out in chunks of `chunk_size`, until it
+/// runs dry or `shared` is cancelled.
+pub async fn batch_insertion<R: Repository>(
+    connection: R,
+    worker_name: String,
+    mut source: WorkSource<R::Entity>,
+    chunk_size: usize,
+    args: InsertArgs,
+    shared: SharedRun,
+) -> Result<WorkerStats, DbError> {
+    let SharedRun {
+        inflight,
+        progress,
+        exhausted,
+        worker_rate,
+        committed,
+        cancel,
+    } = shared;
+    let start = Instant::now();
+    let mut stats = WorkerStats::new(worker_name, args.latency);
+    let mut errors = 0;
+    // Rows taken from the source so far, which sets when the next chunk is
+    // due under --target-rate.
+    let mut taken = 0;
+
+    while !cancel.is_cancelled() {
+        let chunk = source.next_chunk(chunk_size).await;
+        if chunk.is_empty() {
+            break;
+        }
+        if let Some(rate) = worker_rate {
+            let due = start + Duration::from_secs_f64(taken as f64 / rate);
+            tokio::select! {
+                _ = tokio::time::sleep_until(due.into()) => {}
+                _ = cancel.cancelled() => break,
+            }
+        }
+        taken += chunk.len();
+
+        // The semaphore is never closed, so acquiring can't fail.
+        let permit = inflight.acquire().await.expect("semaphore closed");
+        let call_start = stats.latency.is_some().then(Instant::now);
+        let result = connection
+            .insert(chunk.clone(), args.insert_options(), cancel.clone())
+            .await;
+        if let (Some(histogram), Some(call_start)) = (&mut stats.latency, call_start) {
+            histogram.saturating_record(call_start.elapsed().as_micros() as u64);
+        }
+        drop(permit);
+
+        match result {
+            Ok(outcome) => {
+                if !args.quiet {
+                    for user in &chunk[..outcome.attempted] {
+                        debug!(?user, "inserted");
+                    }
+                    debug!(last_rowid = ?outcome.last_rowid, "committed");
+                }
+                progress.fetch_add(outcome.attempted as u64, Ordering::Relaxed);
+                committed.fetch_add(outcome.attempted as u64, Ordering::Relaxed);
+                stats.inserted += outcome.inserted;
+                stats.ignored += outcome.attempted - outcome.inserted;
+                if outcome.retries > 0 {
+                    stats.retried += 1;
+                }
+            }
+            Err(e) if args.strict => {
+                cancel.cancel();
+                return Err(e);
+            }
+            Err(e) => {
+                // The whole transaction was rolled back, so none of the
+                // chunk's rows made it in.
+                stats.failed += chunk.len();
+                errors += 1;
+                if e.is_busy() {
+                    stats.exhausted += 1;
+                    let total = exhausted.fetch_add(1, Ordering::Relaxed) + 1;
+                    if args.fail_after_retries.is_some_and(|limit| total > limit) {
+                        cancel.cancel();
+                    }
+                }
+                if errors <= MAX_LOGGED_ERRORS {
+                    if let DbError::UniqueViolation(_) = e {
+                        error!("{}; rerun with --on-conflict ignore or replace", e);
+                    } else {
+                        error!("{}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    if errors > MAX_LOGGED_ERRORS {
+        error!(
+            "{} more insert errors not shown",
+            errors - MAX_LOGGED_ERRORS
+        );
+    }
+
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+/// A latency histogram in microseconds with 3 significant figures, growing as
+/// needed so no sample is ever out of range.
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new(3).expect("3 significant figures is a valid precision")
+}
+
+fn is_unique_violation(err: &tokio_rusqlite::Error) -> bool {
+    matches!(
+        err,
+        tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _))
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+    )
+}
+
+fn is_foreign_key_violation(err: &tokio_rusqlite::Error) -> bool {
+    matches!(
+        err,
+        tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _))
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY
+    )
+}
+
+fn is_check_violation(err: &tokio_rusqlite::Error) -> bool {
+    matches!(
+        err,
+        tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _))
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_CHECK
+    )
+}
+
+/// Inserts `users` in one transaction, `options.rows_per_stmt` rows per
+/// statement, stopping early once `cancel` fires.
+fn insert_user_rows(
+    conn: &mut rusqlite::Connection,
+    schema: &Schema,
+    users: &[User],
+    options: InsertOptions,
+    cancel: &CancellationToken,
+) -> rusqlite::Result<InsertOutcome> {
+    let rows_per_stmt = schema.rows_per_stmt(options.rows_per_stmt);
+    // Dropping the transaction without committing rolls it back, so any
+    // failed insert below discards the whole batch.
+    let tx = conn.transaction_with_behavior(options.begin_mode.behavior())?;
+    let mut outcome = InsertOutcome::default();
+    for rows in users.chunks(rows_per_stmt) {
+        if cancel.is_cancelled() {
+            break;
+        }
+        // Every full chunk shares one cached statement; only a short final
+        // chunk needs a second one.
+        let sql = schema.insert_sql(options.on_conflict, rows.len());
+        let mut stmt = tx.prepare_cached(&sql)?;
+        let names: Vec<(String, ToSqlOutput)> = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(row, user)| {
+                user.named_values(schema)
+                    .map(move |(column, value)| (Schema::insert_param(column, row), value))
+            })
+            .collect();
+        let params: Vec<(&str, &dyn rusqlite::ToSql)> = names
+            .iter()
+            .map(|(name, value)| (name.as_str(), value as &dyn rusqlite::ToSql))
+            .collect();
+        let inserted = stmt.execute(params.as_slice())?;
+        if inserted > 0 {
+            outcome.last_rowid = Some(tx.last_insert_rowid());
+        }
+        outcome.inserted += inserted;
+        outcome.attempted += rows.len();
+    }
+    tx.commit()?;
+
+    Ok(outcome)
+}
+
+/// Backoff before busy retry `attempt` (counting from 0): doubling from
+/// `--retry-base-ms`, capped at `MAX_RETRY_DELAY`.
+fn retry_delay(options: &DbOptions, attempt: u32) -> Duration {
+    Duration::from_millis(options.retry_base_ms)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Formats an error SQLite itself reported as `code/extended/message`, e.g.
+/// `ConstraintViolation/2067/UNIQUE constraint failed: users.name`, so a
+/// UNIQUE violation, a busy database and a full disk can be told apart in the
+/// logs. Other errors keep their own message.
+fn describe_sqlite_error(err: &rusqlite::Error) -> String {
+    match err {
+        rusqlite::Error::SqliteFailure(e, message) => format!(
+            "{:?}/{}/{}",
+            e.code,
+            e.extended_code,
+            message.clone().unwrap_or_else(|| e.to_string())
+        ),
+        other => other.to_string(),
+    }
+}
+
+/// `describe_sqlite_error` for an error from a `Connection::call`.
+fn describe_call_error(err: &tokio_rusqlite::Error) -> String {
+    match err {
+        tokio_rusqlite::Error::Rusqlite(e) => describe_sqlite_error(e),
+        other => other.to_string(),
+    }
+}
+
+fn is_busy(err: &tokio_rusqlite::Error) -> bool {
+    matches!(
+        err,
+        tokio_rusqlite::Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _))
+            if matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_hands_out_every_entity_once() {
+        for len in 0..40 {
+            for n_workers in 1..12 {
+                let slices = partition((0..len).collect(), n_workers);
+                assert_eq!(slices.len(), n_workers);
+                let mut dealt: Vec<_> = slices.iter().flatten().copied().collect();
+                dealt.sort_unstable();
+                assert_eq!(
+                    dealt,
+                    (0..len).collect::<Vec<_>>(),
+                    "{} entities over {} workers",
+                    len,
+                    n_workers
+                );
+                let sizes: Vec<_> = slices.iter().map(Vec::len).collect();
+                let spread = sizes.iter().max().unwrap() - sizes.iter().min().unwrap();
+                assert!(spread <= 1, "uneven slices {:?}", sizes);
+            }
+        }
+    }
+
+    /// The SQL `--dry-run` prints for the command line `args`.
+    fn plan(args: &[&str]) -> Vec<String> {
+        let cli =
+            Args::try_parse_from(std::iter::once("mult_sql").chain(args.iter().copied())).unwrap();
+        let schema = Schema::new(
+            cli.schema.table,
+            cli.schema.extra_cols,
+            !cli.schema.no_unique,
+            cli.schema.max_name_len,
+            cli.schema.with_payload.is_some(),
+            cli.schema.name_type,
+        );
+        planned_sql(&cli.command, &schema, !cli.no_create)
+    }
+
+    const CREATE_USERS: &str = "CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY key,
+    name TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+)";
+
+    #[test]
+    fn insert_creates_the_table_then_inserts() {
+        assert_eq!(
+            plan(&["insert", "--rows-per-stmt", "2"]),
+            [
+                CREATE_USERS,
+                "INSERT INTO users (name, email, created_at) VALUES \
+                 (:name_0, :email_0, :created_at_0),(:name_1, :email_1, :created_at_1)",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_create_leaves_out_the_create_table() {
+        assert_eq!(
+            plan(&[
+                "--no-create",
+                "insert",
+                "--insert-mode",
+                "truncate",
+                "--rows-per-stmt",
+                "1"
+            ]),
+            [
+                "DELETE FROM users",
+                "INSERT INTO users (name, email, created_at) VALUES \
+                 (:name_0, :email_0, :created_at_0)",
+            ]
+        );
+        assert_eq!(
+            plan(&[
+                "--no-create",
+                "--no-unique",
+                "insert",
+                "--rows-per-stmt",
+                "1",
+                "--on-conflict",
+                "replace"
+            ]),
+            [
+                "INSERT OR REPLACE INTO users (name, email, created_at) VALUES \
+              (:name_0, :email_0, :created_at_0)"
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_options_shape_the_create_table() {
+        assert_eq!(
+            plan(&[
+                "--extra-cols",
+                "2",
+                "--max-name-len",
+                "20",
+                "insert",
+                "--rows-per-stmt",
+                "1",
+                "--on-conflict",
+                "ignore",
+            ]),
+            [
+                "CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY key,
+    name TEXT NOT NULL UNIQUE CHECK(length(name) <= 20),
+    email TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    extra_1 TEXT,
+    extra_2 TEXT
+)",
+                "INSERT OR IGNORE INTO users (name, email, created_at, extra_1, extra_2) VALUES \
+                 (:name_0, :email_0, :created_at_0, :extra_1_0, :extra_2_0)",
+            ]
+        );
+    }
+
+    #[test]
+    fn create_index_names_the_index_after_the_table() {
+        assert_eq!(
+            plan(&["create-index", "name"]),
+            [
+                CREATE_USERS,
+                "CREATE INDEX IF NOT EXISTS main.idx_users_name ON users (name)"
+            ]
+        );
+        assert_eq!(
+            plan(&["--no-create", "--table", "people", "create-index", "name"]),
+            ["CREATE INDEX IF NOT EXISTS main.idx_people_name ON people (name)"]
+        );
+    }
+
+    #[test]
+    fn explain_prefixes_the_query() {
+        assert_eq!(
+            plan(&[
+                "--no-create",
+                "explain",
+                "SELECT name FROM users WHERE id = 1"
+            ]),
+            ["EXPLAIN QUERY PLAN SELECT name FROM users WHERE id = 1"]
+        );
+    }
+
+    #[test]
+    fn vacuum_truncates_the_wal_afterwards() {
+        assert_eq!(
+            plan(&["vacuum"]),
+            [CREATE_USERS, "VACUUM", "PRAGMA wal_checkpoint(TRUNCATE)"]
+        );
+    }
+
+    fn users_schema() -> Arc<Schema> {
+        Arc::new(Schema::new(
+            "users".to_owned(),
+            0,
+            true,
+            None,
+            false,
+            NameType::Text,
+        ))
+    }
+
+    async fn memory_db() -> DB {
+        let db = DB::new(Path::new(MEMORY_DB), DbOptions::default(), users_schema())
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn seeded_users(count: usize, seed: u64) -> Vec<User> {
+        create_users(
+            count,
+            UserShape::default(),
+            &mut StdRng::seed_from_u64(seed),
+        )
+    }
+
+    /// Inserts through `DB` but panics on a user named `PANIC_NAME`.
+    #[derive(Clone)]
+    struct PanicOnName(DB);
+
+    const PANIC_NAME: &str = "panic";
+
+    impl Repository for PanicOnName {
+        type Entity = User;
+
+        fn unique_key(user: &User) -> &str {
+            &user.name
+        }
+
+        async fn enforces_unique(&self) -> Result<bool, DbError> {
+            self.0.name_is_unique().await
+        }
+
+        async fn insert(
+            &self,
+            users: Vec<User>,
+            options: InsertOptions,
+            cancel: CancellationToken,
+        ) -> Result<InsertOutcome, DbError> {
+            assert!(users.iter().all(|user| user.name != PANIC_NAME), "bad user");
+            self.0.insert_many(users, options, cancel).await
+        }
+
+        async fn count(&self) -> Result<i64, DbError> {
+            self.0.count_users(None, Vec::new()).await
+        }
+
+        async fn delete_all(&self) -> Result<(), DbError> {
+            self.0.delete_all_users().await
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_worker_fails_the_run_with_its_committed_rows() {
+        let db = memory_db().await;
+        let pool = DbPool::open(db, Path::new(MEMORY_DB), 2, 2).await.unwrap();
+        let mut users = seeded_users(20, 95);
+        // Worker 2 is dealt the odd indexes, so this is its sixth user.
+        users[11].name = PANIC_NAME.to_owned();
+        let args = InsertArgs {
+            tx_size: Some(1),
+            quiet: true,
+            ..InsertArgs::default()
+        };
+
+        let err = run_insertion(
+            pool.map(PanicOnName),
+            Workload::Fixed(users),
+            2,
+            args,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "worker 2 panicked after committing 5 rows; see the log for the panic"
+        );
+    }
+
+    #[derive(Clone, Debug)]
+    struct Product {
+        sku: String,
+        price: i64,
+    }
+
+    /// A `products` table on a `DB`'s connection, to drive the insertion
+    /// workers with something other than users.
+    #[derive(Clone)]
+    struct Products(DB);
+
+    impl Products {
+        async fn create(db: DB) -> Self {
+            db.conn
+                .call(|conn| {
+                    conn.execute_batch(
+                        "CREATE TABLE products (
+                            id INTEGER PRIMARY KEY,
+                            sku TEXT NOT NULL UNIQUE,
+                            price INTEGER NOT NULL
+                        )",
+                    )?;
+                    Ok(())
+                })
+                .await
+                .unwrap();
+            Self(db)
+        }
+    }
+
+    impl Repository for Products {
+        type Entity = Product;
+
+        fn unique_key(product: &Product) -> &str {
+            &product.sku
+        }
+
+        async fn enforces_unique(&self) -> Result<bool, DbError> {
+            Ok(true)
+        }
+
+        async fn insert(
+            &self,
+            products: Vec<Product>,
+            _options: InsertOptions,
+            _cancel: CancellationToken,
+        ) -> Result<InsertOutcome, DbError> {
+            self.0
+                .conn
+                .call(move |conn| {
+                    let tx = conn.transaction()?;
+                    for product in &products {
+                        tx.prepare_cached("INSERT INTO products (sku, price) VALUES (?1, ?2)")?
+                            .execute(rusqlite::params![product.sku, product.price])?;
+                    }
+                    let last_rowid = tx.last_insert_rowid();
+                    tx.commit()?;
+                    Ok(InsertOutcome {
+                        attempted: products.len(),
+                        inserted: products.len(),
+                        last_rowid: Some(last_rowid),
+                        retries: 0,
+                    })
+                })
+                .await
+                .map_err(DbError::from_insert)
+        }
+
+        async fn count(&self) -> Result<i64, DbError> {
+            Ok(self
+                .0
+                .conn
+                .call(|conn| {
+                    Ok(conn.query_row("SELECT COUNT(*) FROM products", [], |row| row.get(0))?)
+                })
+                .await?)
+        }
+
+        async fn delete_all(&self) -> Result<(), DbError> {
+            self.0
+                .conn
+                .call(|conn| Ok(conn.execute("DELETE FROM products", []).map(drop)?))
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_insertion_works_through_any_repository() {
+        let products = Products::create(memory_db().await).await;
+        let catalogue: Vec<_> = (0..250)
+            .map(|i| Product {
+                sku: format!("SKU-{:04}", i),
+                price: i * 10,
+            })
+            .collect();
+
+        let stats = batch_insertion(
+            products.clone(),
+            "Worker: 1".to_owned(),
+            WorkSource::Static(catalogue.into_iter()),
+            100,
+            InsertArgs::default(),
+            SharedRun::new(None, CancellationToken::new()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.inserted, 250);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(products.count().await.unwrap(), 250);
+        products.delete_all().await.unwrap();
+        assert_eq!(products.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn insert_many_reuses_its_statement_across_calls() {
+        let db = memory_db().await;
+        let options = InsertArgs::default().insert_options();
+        let mut users = seeded_users(2000, 3).into_iter();
+
+        for batch in 1..=2 {
+            let outcome = db
+                .insert_many(
+                    users.by_ref().take(1000).collect(),
+                    options,
+                    CancellationToken::new(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(outcome.inserted, 1000);
+            assert_eq!(
+                db.count_users(None, Vec::new()).await.unwrap(),
+                batch * 1000
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_status_counts_rows_apart_from_calls() {
+        let mut db = memory_db().await;
+        let profile = Arc::new(Profile::new());
+        db.profile = Some(Arc::clone(&profile));
+
+        db.insert_many(
+            seeded_users(250, 79),
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        db.delete_users(Some("id <= 10".to_owned()), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(db.select_ids().await.unwrap().len(), 240);
+
+        let status = profile.to_json();
+        assert_eq!(status["insert_calls"], 1);
+        assert_eq!(status["insert_rows"], 250);
+        assert_eq!(status["delete_calls"], 1);
+        assert_eq!(status["delete_rows"], 10);
+        assert_eq!(status["select_calls"], 1);
+        assert_eq!(status["select_rows"], 240);
+        assert_eq!(status["update_calls"], 0);
+    }
+
+    #[tokio::test]
+    async fn unique_violations_are_counted_as_failed() {
+        let db = memory_db().await;
+        let users = seeded_users(10, 5);
+        db.insert_many(
+            users[..3].to_vec(),
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let args = InsertArgs {
+            quiet: true,
+            ..InsertArgs::default()
+        };
+
+        let stats = batch_insertion(
+            db.clone(),
+            "Worker: 1".to_owned(),
+            WorkSource::Static(users.into_iter()),
+            1,
+            args,
+            SharedRun::new(None, CancellationToken::new()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.failed, 3);
+        assert_eq!(stats.inserted, 7);
+        assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn csv_output_quotes_names_and_goes_to_the_output_file() {
+        let db = memory_db().await;
+        let user = User::new(
+            "Smith, \"Jo\"".to_owned(),
+            "jo@example.com".to_owned(),
+            1_700_000_000,
+            Vec::new(),
+            None,
+        );
+        db.insert_many(
+            vec![user],
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.csv");
+
+        let out = output_writer(Some(&path)).unwrap();
+        let n_rows = write_users(
+            &db,
+            OutputFormat::Csv,
+            None,
+            &RowOrder::Scan,
+            out,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(n_rows, 1);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "id,name,email,created_at\n1,\"Smith, \"\"Jo\"\"\",jo@example.com,1700000000\n"
+        );
+    }
+
+    async fn file_db(path: &Path, options: DbOptions) -> DB {
+        let db = DB::new(path, options, users_schema()).await.unwrap();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    /// Holds the write lock on `path` from another connection until `release`
+    /// fires, returning once the lock is taken.
+    fn hold_write_lock(path: &Path, release: std::sync::mpsc::Receiver<()>) {
+        let conn = rusqlite::Connection::open(path).unwrap();
+        let (locked, wait) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+            locked.send(()).unwrap();
+            let _ = release.recv();
+            conn.execute_batch("COMMIT").unwrap();
+        });
+        wait.recv().unwrap();
+    }
+
+    fn retrying_options(max_retries: u32) -> DbOptions {
+        DbOptions {
+            busy_timeout_ms: 0,
+            max_retries,
+            retry_base_ms: 20,
+            ..DbOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_busy_insert_backs_off_until_the_lock_is_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("busy.db");
+        let db = file_db(&path, retrying_options(5)).await;
+        let (release, released) = std::sync::mpsc::channel();
+        hold_write_lock(&path, released);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            release.send(()).unwrap();
+        });
+
+        let outcome = db
+            .insert_many(
+                seeded_users(10, 15),
+                InsertArgs::default().insert_options(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert!(outcome.retries > 0);
+        assert_eq!(outcome.inserted, 10);
+    }
+
+    #[tokio::test]
+    async fn a_busy_insert_gives_up_after_its_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("busy.db");
+        let db = file_db(&path, retrying_options(2)).await;
+        let (_release, released) = std::sync::mpsc::channel();
+        hold_write_lock(&path, released);
+
+        let err = db
+            .insert_many(
+                seeded_users(10, 15),
+                InsertArgs::default().insert_options(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(err.is_busy(), "{}", err);
+        assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn select_reads_columns_by_name_after_a_migration_reorders_them() {
+        let schema = Schema::new("users".to_owned(), 1, true, None, false, NameType::Text);
+        let db = DB::new(Path::new(MEMORY_DB), DbOptions::default(), Arc::new(schema))
+            .await
+            .unwrap();
+        // A version 1 table, so the migration appends email and created_at
+        // after extra_1 rather than before it.
+        db.conn
+            .call(|conn| {
+                conn.execute_batch(
+                    "CREATE TABLE users (
+                        id INTEGER PRIMARY key,
+                        name TEXT NOT NULL UNIQUE,
+                        extra_1 TEXT
+                    );
+                    INSERT INTO users (id, name, extra_1) VALUES (7, 'ada', 'x');",
+                )?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+        let user = User::new(
+            "grace".to_owned(),
+            "grace@example.com".to_owned(),
+            42,
+            vec!["y".to_owned()],
+            None,
+        );
+        db.insert_many(
+            vec![user],
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let users = db.select_all_users().await.unwrap();
+
+        let rows: Vec<_> = users
+            .iter()
+            .map(|u| (u.id, u.name.as_str(), u.email.as_str(), u.created_at))
+            .collect();
+        assert_eq!(
+            rows,
+            [(7, "ada", "", 0), (8, "grace", "grace@example.com", 42)]
+        );
+    }
+
+    #[tokio::test]
+    async fn ids_past_i32_max_round_trip() {
+        let db = memory_db().await;
+        let big = i64::from(i32::MAX) * 2;
+        db.conn
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO users (id, name, email, created_at) \
+                     VALUES (?1, 'ada', 'ada@example.com', 0)",
+                    [big],
+                )?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let outcome = db
+            .insert_many(
+                seeded_users(1, 22),
+                InsertArgs::default().insert_options(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(outcome.last_rowid, Some(big + 1));
+        let ids: Vec<_> = db
+            .select_all_users()
+            .await
+            .unwrap()
+            .iter()
+            .map(|u| u.id)
+            .collect();
+        assert_eq!(ids, [big, big + 1]);
+        assert_eq!(
+            db.find_user("ada".to_owned()).await.unwrap().unwrap().id,
+            big
+        );
+    }
+
+    #[tokio::test]
+    async fn a_short_last_statement_still_inserts_its_rows() {
+        let db = memory_db().await;
+        let users = seeded_users(250, 26);
+        let options = InsertOptions {
+            rows_per_stmt: 100,
+            ..InsertArgs::default().insert_options()
+        };
+
+        let outcome = db
+            .insert_many(users.clone(), options, CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!((outcome.attempted, outcome.inserted), (250, 250));
+        let names: Vec<_> = db
+            .select_all_users()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|u| u.name)
+            .collect();
+        let expected: Vec<_> = users.into_iter().map(|u| u.name).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[tokio::test]
+    async fn last_rowids_increase_across_inserts() {
+        let db = memory_db().await;
+        let options = InsertArgs::default().insert_options();
+        let mut rowids = Vec::new();
+        for user in seeded_users(5, 41) {
+            let outcome = db
+                .insert_many(vec![user], options, CancellationToken::new())
+                .await
+                .unwrap();
+            rowids.push(outcome.last_rowid.unwrap());
+        }
+
+        assert!(rowids.windows(2).all(|w| w[0] < w[1]), "{:?}", rowids);
+        let ids: Vec<_> = db
+            .select_all_users()
+            .await
+            .unwrap()
+            .iter()
+            .map(|u| u.id)
+            .collect();
+        assert_eq!(ids, rowids);
+    }
+
+    #[tokio::test]
+    async fn the_pool_is_capped_at_max_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pool.db");
+        let db = file_db(&path, DbOptions::default()).await;
+
+        let pool = DbPool::open(db.clone(), &path, 2, 3).await.unwrap();
+        assert_eq!(pool.dbs.len(), 2);
+        let pool = DbPool::open(db.clone(), &path, 8, 3).await.unwrap();
+        assert_eq!(pool.dbs.len(), 3);
+
+        // Eight workers take turns on the three connections.
+        let args = InsertArgs {
+            tx_size: Some(10),
+            quiet: true,
+            ..InsertArgs::default()
+        };
+        let report = run_insertion(
+            pool,
+            Workload::Fixed(seeded_users(800, 46)),
+            8,
+            args,
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.workers().len(), 8);
+        assert_eq!(report.inserted(), 800);
+        assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 800);
+    }
+
+    #[tokio::test]
+    async fn limit_caps_the_selected_rows() {
+        let db = memory_db().await;
+        db.insert_many(
+            seeded_users(20, 64),
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        for order in [RowOrder::Scan, RowOrder::Random] {
+            let mut out = Vec::new();
+            let n_rows = write_users(
+                &db,
+                OutputFormat::Jsonl,
+                Some(5),
+                &order,
+                &mut out,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(n_rows, 5);
+            assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 5);
+        }
+    }
+
+    #[test]
+    fn sample_needs_a_limit() {
+        assert!(Args::try_parse_from(["mult_sql", "select", "--sample"]).is_err());
+        assert!(Args::try_parse_from(["mult_sql", "select", "--sample", "--limit", "5"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_delete_racing_the_inserts_leaves_a_consistent_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mix.db");
+        let db = file_db(&path, DbOptions::default()).await;
+        db.insert_many(
+            seeded_users(100, 67),
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let pool = DbPool::open(db.clone(), &path, 3, 3).await.unwrap();
+        let args = MixArgs {
+            duration_secs: 2,
+            readers: 1,
+            writers: 2,
+            tx_size: 5,
+            count_only: true,
+            delete_after_secs: Some(1),
+        };
+
+        // run_mix itself fails unless the count adds up after the delete.
+        run_mix(pool, args, UserShape::default(), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(db.integrity_check().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_mix_stops_before_its_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mix.db");
+        let db = file_db(&path, DbOptions::default()).await;
+        let pool = DbPool::open(db.clone(), &path, 3, 3).await.unwrap();
+        let args = MixArgs {
+            duration_secs: 60,
+            readers: 1,
+            writers: 2,
+            tx_size: 5,
+            count_only: true,
+            delete_after_secs: Some(30),
+        };
+        let cancel = CancellationToken::new();
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                cancel.cancel();
+            }
+        });
+
+        let start = Instant::now();
+        run_mix(pool, args, UserShape::default(), &cancel)
+            .await
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        // The delete never ran, so every committed insert is still there.
+        assert!(db.count_users(None, Vec::new()).await.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_concurrent_update_renames_nothing() {
+        let db = memory_db().await;
+        db.insert_many(
+            seeded_users(50, 25),
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let pool = DbPool::open(db.clone(), Path::new(MEMORY_DB), 2, 2)
+            .await
+            .unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let n_rows = run_update(pool, "x_".to_owned(), 2, &cancel).await.unwrap();
+
+        assert_eq!(n_rows, 0);
+        let names = db.select_names().await.unwrap();
+        assert!(names.iter().all(|name| !name.starts_with("x_")));
+    }
+
+    #[tokio::test]
+    async fn a_post_for_a_missing_user_is_a_foreign_key_violation() {
+        let db = memory_db().await;
+        db.create_posts_table().await.unwrap();
+        let outcome = db
+            .insert_many(
+                seeded_users(1, 68),
+                InsertArgs::default().insert_options(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let user_id = outcome.last_rowid.unwrap();
+        let post = |user_id| Post {
+            user_id,
+            title: "hello".to_owned(),
+        };
+        let options = InsertArgs::default().insert_options();
+
+        db.insert_posts(vec![post(user_id)], options, CancellationToken::new())
+            .await
+            .unwrap();
+        let err = db
+            .insert_posts(vec![post(user_id + 1)], options, CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, DbError::ForeignKeyViolation(_)), "{}", err);
+        assert_eq!(db.count_posts().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn json_rows_keep_every_storage_class() {
+        let db = memory_db().await;
+
+        let rows = db
+            .select_rows_json(
+                "SELECT 42 AS i, 1.5 AS r, 'hi' AS t, x'00ff10' AS b, NULL AS n".to_owned(),
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            [serde_json::json!({"i": 42, "r": 1.5, "t": "hi", "b": "AP8Q", "n": null})]
+        );
+    }
+
+    #[test]
+    fn zero_workers_are_rejected() {
+        assert!(Args::try_parse_from(["mult_sql", "-w", "0", "insert"]).is_err());
+        assert!(Args::try_parse_from(["mult_sql", "-w", "1", "insert"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn an_exclusive_lock_held_elsewhere_gives_locked_after_the_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.db");
+        let holder = rusqlite::Connection::open(&path).unwrap();
+        holder
+            .execute_batch("CREATE TABLE t (x); BEGIN EXCLUSIVE; INSERT INTO t VALUES (1);")
+            .unwrap();
+        let options = DbOptions {
+            busy_timeout_ms: 0,
+            open_retries: 2,
+            ..DbOptions::default()
+        };
+
+        let opened = DB::new(&path, options, users_schema()).await;
+
+        assert!(matches!(opened, Err(DbError::Locked { attempts: 3, .. })));
+        holder.execute_batch("COMMIT").unwrap();
+        DB::new(&path, DbOptions::default(), users_schema())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_open_retries_until_the_exclusive_lock_is_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.db");
+        let holder = rusqlite::Connection::open(&path).unwrap();
+        holder
+            .execute_batch("CREATE TABLE t (x); BEGIN EXCLUSIVE; INSERT INTO t VALUES (1);")
+            .unwrap();
+        let hold = Duration::from_millis(100);
+        std::thread::spawn(move || {
+            std::thread::sleep(hold);
+            holder.execute_batch("COMMIT").unwrap();
+        });
+        let options = DbOptions {
+            busy_timeout_ms: 0,
+            open_retries: 8,
+            retry_base_ms: 20,
+            ..DbOptions::default()
+        };
+        let start = Instant::now();
+
+        DB::new(&path, options, users_schema())
+            .await
+            .map(drop)
+            .unwrap();
+
+        assert!(start.elapsed() >= hold);
+    }
+
+    #[tokio::test]
+    async fn strict_stops_at_the_first_unique_violation() {
+        let db = memory_db().await;
+        let users = seeded_users(10, 83);
+        db.insert_many(
+            vec![users[2].clone()],
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        let args = InsertArgs {
+            strict: true,
+            quiet: true,
+            ..InsertArgs::default()
+        };
+        let cancel = CancellationToken::new();
+
+        let result = batch_insertion(
+            db.clone(),
+            "Worker: 1".to_owned(),
+            WorkSource::Static(users.into_iter()),
+            1,
+            args,
+            SharedRun::new(None, cancel.clone()),
+        )
+        .await;
+
+        assert!(matches!(result, Err(DbError::UniqueViolation(_))));
+        assert!(cancel.is_cancelled());
+        assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 3);
+    }
+
+    async fn memory_db_with(schema: Schema) -> DB {
+        let db = DB::new(Path::new(MEMORY_DB), DbOptions::default(), Arc::new(schema))
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn a_check_violation_is_a_constraint_violation() {
+        let schema = Schema::new("users".to_owned(), 0, true, Some(4), false, NameType::Text);
+        let db = memory_db_with(schema).await;
+        let user = |name: &str| User::new(name.to_owned(), "a@b.c".to_owned(), 0, Vec::new(), None);
+        let options = InsertArgs::default().insert_options();
+
+        db.insert_many(vec![user("abcd")], options, CancellationToken::new())
+            .await
+            .unwrap();
+        let err = db
+            .insert_many(vec![user("abcde")], options, CancellationToken::new())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, DbError::ConstraintViolation(_)), "{}", err);
+        assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn every_value_lands_in_its_own_column() {
+        let schema = Schema::new("users".to_owned(), 2, true, None, false, NameType::Text);
+        let db = memory_db_with(schema).await;
+        let users = (1..=2)
+            .map(|i| {
+                User::new(
+                    format!("name{}", i),
+                    format!("email{}@example.com", i),
+                    i,
+                    vec![format!("first{}", i), format!("second{}", i)],
+                    None,
+                )
+            })
+            .collect();
+
+        db.insert_many(
+            users,
+            InsertArgs::default().insert_options(),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+
+        let rows: Vec<(String, String, i64, String, String)> = db
+            .conn
+            .call(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT name, email, created_at, extra_1, extra_2 FROM users ORDER BY id",
+                )?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    })?
+                    .collect::<rusqlite::Result<_>>()?;
+                Ok(rows)
+            })
+            .await
+            .unwrap();
+        let expected: Vec<_> = (1..=2)
+            .map(|i| {
+                (
+                    format!("name{}", i),
+                    format!("email{}@example.com", i),
+                    i,
+                    format!("first{}", i),
+                    format!("second{}", i),
+                )
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[tokio::test]
+    async fn names_round_trip_as_text_and_as_blob() {
+        for (name_type, storage) in [(NameType::Text, "text"), (NameType::Blob, "blob")] {
+            let schema = Schema::new("users".to_owned(), 0, true, None, false, name_type);
+            let db = memory_db_with(schema).await;
+            let user = User::new(
+                "Zoë".to_owned(),
+                "z@example.com".to_owned(),
+                0,
+                Vec::new(),
+                None,
+            );
+
+            db.insert_many(
+                vec![user],
+                InsertArgs::default().insert_options(),
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+            let stored: String = db
+                .conn
+                .call(|conn| {
+                    Ok(conn.query_row("SELECT typeof(name) FROM users", [], |row| row.get(0))?)
+                })
+                .await
+                .unwrap();
+            assert_eq!(stored, storage);
+            let names: Vec<_> = db
+                .select_all_users()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|u| u.name)
+                .collect();
+            assert_eq!(names, ["Zoë"]);
+            let found = db.find_user("Zoë".to_owned()).await.unwrap();
+            assert_eq!(found.map(|u| u.name).as_deref(), Some("Zoë"));
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_cache_connections_see_each_others_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.db");
+        let options = DbOptions {
+            shared_cache: true,
+            ..DbOptions::default()
+        };
+        let first = file_db(&path, options.clone()).await;
+        let second = file_db(&path, options).await;
+        let mut users = seeded_users(30, 98).into_iter();
+        let insert_options = InsertArgs::default().insert_options();
+
+        first
+            .insert_many(
+                users.by_ref().take(10).collect(),
+                insert_options,
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(second.count_users(None, Vec::new()).await.unwrap(), 10);
+        second
+            .insert_many(users.collect(), insert_options, CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(first.count_users(None, Vec::new()).await.unwrap(), 30);
+    }
+}
@@ -0,0 +1,203 @@
+//! Versioned schema changes, tracked per table in a `schema_versions` table
+//! next to it, since one file can hold tables at different versions.
+//!
+//! Migration `n` (counting from 1) takes a table from version `n - 1` to `n`.
+//! A table that doesn't exist yet is created in its latest shape straight
+//! away, so the list only matters for tables made by older builds. A table
+//! with no version recorded is taken to be at version 0.
+
+use crate::Schema;
+use rusqlite::OptionalExtension;
+
+/// Version a fully migrated table reports.
+pub(crate) const LATEST: i64 = 2;
+
+/// What `migrate` did.
+#[derive(Debug)]
+pub(crate) enum Migrated {
+    Created,
+    Upgraded { from: i64 },
+    UpToDate,
+}
+
+/// The statements for each migration, in order.
+fn migrations(schema: &Schema) -> Vec<String> {
+    let table = &schema.table;
+    let mut columns = vec![
+        "id INTEGER PRIMARY key".to_owned(),
+        "name TEXT NOT NULL UNIQUE".to_owned(),
+    ];
+    columns.extend(schema.extra_columns.iter().map(|c| format!("{} TEXT", c)));
+
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n    {}\n)",
+            table,
+            columns.join(",\n    ")
+        ),
+        format!(
+            "ALTER TABLE {table} ADD COLUMN email TEXT NOT NULL DEFAULT '';
+             ALTER TABLE {table} ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0"
+        ),
+    ]
+}
+
+/// Brings the table up to `LATEST` in one transaction.
+pub(crate) fn migrate(
+    conn: &mut rusqlite::Connection,
+    schema: &Schema,
+) -> rusqlite::Result<Migrated> {
+    let (database, table) = schema.split_table();
+    let tx = conn.transaction()?;
+    tx.execute(&create_versions_sql(database), ())?;
+    let version: i64 = tx
+        .query_row(&select_version_sql(database), [table], |row| row.get(0))
+        .optional()?
+        .unwrap_or(0);
+    let exists: bool = tx.query_row(&schema.table_exists_sql(), [table], |row| row.get(0))?;
+
+    let migrated = if !exists {
+        tx.execute(&schema.create_table_sql(), ())?;
+        Migrated::Created
+    } else if version < LATEST {
+        for sql in &migrations(schema)[version.max(0) as usize..] {
+            tx.execute_batch(sql)?;
+        }
+        Migrated::Upgraded { from: version }
+    } else {
+        Migrated::UpToDate
+    };
+    if version < LATEST {
+        set_version(&tx, schema)?;
+    }
+    tx.commit()?;
+
+    Ok(migrated)
+}
+
+/// Marks the table as fully migrated, for callers that just created it in
+/// its latest shape.
+pub(crate) fn set_version(conn: &rusqlite::Connection, schema: &Schema) -> rusqlite::Result<()> {
+    let (database, table) = schema.split_table();
+    conn.execute(&create_versions_sql(database), ())?;
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {}.schema_versions (table_name, version) VALUES (?1, ?2)",
+            database
+        ),
+        rusqlite::params![table, LATEST],
+    )?;
+
+    Ok(())
+}
+
+fn create_versions_sql(database: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {}.schema_versions \
+         (table_name TEXT PRIMARY KEY, version INTEGER NOT NULL)",
+        database
+    )
+}
+
+/// Reads a table's version, bound to its unqualified name as `?1`.
+fn select_version_sql(database: &str) -> String {
+    format!(
+        "SELECT version FROM {}.schema_versions WHERE table_name = ?1",
+        database
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NameType;
+
+    fn schema(table: &str) -> Schema {
+        Schema::new(table.to_owned(), 0, true, None, false, NameType::Text)
+    }
+
+    fn users() -> Schema {
+        schema("users")
+    }
+
+    fn version(conn: &rusqlite::Connection, table: &str) -> i64 {
+        conn.query_row(&select_version_sql("main"), [table], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn an_old_table_is_upgraded_and_keeps_its_rows() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+             INSERT INTO users (name) VALUES ('ada'), ('grace');",
+        )
+        .unwrap();
+
+        let migrated = migrate(&mut conn, &users()).unwrap();
+        assert!(matches!(migrated, Migrated::Upgraded { from: 0 }));
+        assert_eq!(version(&conn, "users"), LATEST);
+        let rows: Vec<(i64, String, String, i64)> = conn
+            .prepare("SELECT id, name, email, created_at FROM users ORDER BY id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (1, "ada".to_owned(), String::new(), 0),
+                (2, "grace".to_owned(), String::new(), 0)
+            ]
+        );
+
+        let migrated = migrate(&mut conn, &users()).unwrap();
+        assert!(matches!(migrated, Migrated::UpToDate));
+        assert_eq!(version(&conn, "users"), LATEST);
+    }
+
+    #[test]
+    fn a_missing_table_is_created_at_the_latest_version() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        assert!(matches!(
+            migrate(&mut conn, &users()).unwrap(),
+            Migrated::Created
+        ));
+        assert_eq!(version(&conn, "users"), LATEST);
+        assert!(matches!(
+            migrate(&mut conn, &users()).unwrap(),
+            Migrated::UpToDate
+        ));
+    }
+
+    #[test]
+    fn tables_in_one_file_keep_their_own_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conn = rusqlite::Connection::open(dir.path().join("two.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+             INSERT INTO users (name) VALUES ('ada');",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            migrate(&mut conn, &schema("people")).unwrap(),
+            Migrated::Created
+        ));
+        assert!(matches!(
+            migrate(&mut conn, &users()).unwrap(),
+            Migrated::Upgraded { from: 0 }
+        ));
+        assert_eq!(version(&conn, "people"), LATEST);
+        assert_eq!(version(&conn, "users"), LATEST);
+        conn.execute(
+            "INSERT INTO users (name, email, created_at) VALUES ('grace', 'g@example.com', 1)",
+            (),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use mult_sql::{
+    create_users, run_insertion, DbOptions, DbPool, InsertArgs, NameType, Schema, UserShape,
+    Workload, DB,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+fn users_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(
+        "users".to_owned(),
+        0,
+        true,
+        None,
+        false,
+        NameType::Text,
+    ))
+}
+
+async fn open_db(path: &Path) -> DB {
+    let db = DB::new(path, DbOptions::default(), users_schema())
+        .await
+        .unwrap();
+    db.migrate().await.unwrap();
+    db
+}
+
+#[tokio::test]
+async fn pooled_file_connections_keep_every_row() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("pool.db");
+    let db = open_db(&path).await;
+    let pool = DbPool::open(db.clone(), &path, 4, 4).await.unwrap();
+    let users = create_users(4000, UserShape::default(), &mut StdRng::seed_from_u64(61));
+
+    let report = run_insertion(
+        pool,
+        Workload::Fixed(users),
+        4,
+        InsertArgs::default(),
+        CancellationToken::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.inserted(), 4000);
+    assert_eq!(report.failed(), 0);
+    assert!(report.verified());
+    assert_eq!(db.count_users(None, Vec::new()).await.unwrap(), 4000);
+}
+
+#[tokio::test]
+async fn run_insertion_reports_every_worker() {
+    let path = Path::new(":memory:");
+    let db = open_db(path).await;
+    let pool = DbPool::open(db, path, 3, 3).await.unwrap();
+    let users = create_users(300, UserShape::default(), &mut StdRng::seed_from_u64(58));
+    let args = InsertArgs {
+        latency: true,
+        ..InsertArgs::default()
+    };
+
+    let report = run_insertion(
+        pool,
+        Workload::Fixed(users),
+        3,
+        args,
+        CancellationToken::new(),
+    )
+    .await
+    .unwrap();
+
+    let names: Vec<_> = report.workers().iter().map(|w| w.name.as_str()).collect();
+    assert_eq!(names, ["Worker: 1", "Worker: 2", "Worker: 3"]);
+    for worker in report.workers() {
+        assert_eq!(worker.inserted, 100);
+        assert_eq!(worker.ignored, 0);
+        assert_eq!(worker.failed, 0);
+        assert_eq!(worker.latency.as_ref().map(|h| h.len()), Some(1));
+    }
+    assert_eq!(report.inserted(), 300);
+}
+
+#[tokio::test]
+async fn surplus_workers_are_not_started() {
+    let path = Path::new(":memory:");
+    let db = open_db(path).await;
+    let pool = DbPool::open(db, path, 8, 8).await.unwrap();
+    let users = create_users(3, UserShape::default(), &mut StdRng::seed_from_u64(74));
+
+    let report = run_insertion(
+        pool,
+        Workload::Fixed(users),
+        8,
+        InsertArgs::default(),
+        CancellationToken::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.workers().len(), 3);
+    assert!(report.workers().iter().all(|w| w.inserted == 1));
+    assert!(report.verified());
+}